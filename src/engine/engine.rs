@@ -1,13 +1,34 @@
-use std::f32::INFINITY;
+use std::{f32::INFINITY, sync::Arc};
 
+use super::{
+  search::{is_mate, SearchControl, SearchStats, MATE_SCORE, MAX_PLY},
+  see::see,
+  tt::{Bound, TTEntry, TranspositionTable, DEFAULT_HASH_MB},
+};
 use crate::{
   lib::bitcount,
-  movegen::movegen::MoveGen,
+  movegen::{movegen::MoveGen, movelist::MoveList},
   structs::{Board, Line, Move, Pieces, Sides},
 };
 
 pub struct Engine {
-  pub current_depth: u8,
+  pub nodes: u64,
+  // Deepest ply reached from the root
+  pub seldepth: u8,
+  pub stats: SearchStats,
+  // Nodes not yet handed to the shared control
+  batch: u64,
+  // No null moves before this ply, raised while a null move cutoff is verified
+  null_min_ply: usize,
+  // How often each quiet move, by side, start and destination, caused a cutoff lately
+  history: Box<[[[i32; 64]; 64]; 2]>,
+  // The moves from the root to the node being searched
+  played: [Move; MAX_PLY],
+  // Triangular principal variation table, each ply's best line so far from that ply on. A node
+  // builds its line from its best move and the line its child just left one ply further down
+  pv: Box<[Line; MAX_PLY + 1]>,
+  control: Arc<SearchControl>,
+  tt: Arc<TranspositionTable>,
 }
 impl Engine {
   // Piece square tables
@@ -48,11 +69,72 @@ impl Engine {
     14,
   ];
 
+  // Nodes counted between hand-offs to the shared control
+  const NODE_BATCH: u64 = 1024;
+  // One centipawn, scores closer than that are treated as equal
+  const NULL_WINDOW: f32 = 0.01;
+  // Shallower than this a null move search is barely cheaper than the real one
+  const NULL_MIN_DEPTH: u8 = 3;
+  // From here on null move cutoffs are checked with a search of the real moves
+  const NULL_VERIFY_DEPTH: u8 = 8;
+  // History scores stay within plus or minus this
+  const HISTORY_MAX: i32 = 16384;
+  // Captures are ordered above every quiet move when they don't lose material, below when they do
+  const CAPTURE_SCORE: i32 = 1 << 20;
+  // Late quiet moves are reduced from this depth and this many moves in
+  const LMR_MIN_DEPTH: u8 = 3;
+  const LMR_MIN_MOVES: usize = 3;
+  // Up to this depth quiets are pruned once 3 + depth squared moves have been tried
+  const LMP_MAX_DEPTH: u8 = 3;
+
   pub fn new() -> Self {
-    Self { current_depth: 0 }
+    Self::with_control(
+      Arc::new(SearchControl::default()),
+      Arc::new(TranspositionTable::new(DEFAULT_HASH_MB)),
+    )
+  }
+
+  pub fn with_control(control: Arc<SearchControl>, tt: Arc<TranspositionTable>) -> Self {
+    Self {
+      nodes: 0,
+      seldepth: 0,
+      stats: SearchStats::default(),
+      batch: 0,
+      null_min_ply: 0,
+      history: Box::new([[[0; 64]; 64]; 2]),
+      played: [Move::NULL; MAX_PLY],
+      pv: Box::new([Line::new(); MAX_PLY + 1]),
+      control,
+      tt,
+    }
+  }
+
+  fn visit(&mut self, ply: usize) {
+    self.nodes += 1;
+    self.batch += 1;
+    self.seldepth = self.seldepth.max(ply as u8);
+    if self.batch >= self.control.batch_size(Self::NODE_BATCH) {
+      self.control.add_nodes(self.batch);
+      self.batch = 0;
+    }
+  }
+
+  // Hands over the nodes left from the last partial batch, call once the search is done
+  pub fn finish(&mut self) {
+    self.control.record_nodes(self.batch);
+    self.batch = 0;
+  }
+
+  // Scored for the side to move, which has no legal moves
+  fn no_moves_score(board: &Board, ply: usize) -> f32 {
+    if board.in_check() {
+      -(MATE_SCORE - ply as f32)
+    } else {
+      0.0
+    }
   }
 
-  pub fn evaluate(board: Board) -> f32 {
+  pub fn evaluate(board: &Board) -> f32 {
     let bb_pieces = board.bb_pieces;
     let mut score: f32 = 0.0;
     // Material
@@ -106,159 +188,447 @@ impl Engine {
     score
   }
 
-  pub fn pvs(
-    &mut self, board: Board, mut alpha: f32, beta: f32, depth: u8, mut line: Line,
-  ) -> (f32, Line) {
-    if depth == 0 {
-      return (
-        Engine::quiesce(board, alpha, beta)
-          * if board.turn == Sides::WHITE {
-            1.0
-          } else {
-            -1.0
-          },
-        line.clone(),
-      );
-    }
-
-    self.current_depth = self.current_depth + 1;
-
-    for m in MoveGen::gen_moves(board, board.turn, true) {
-      let mut c_line = line.clone();
-      let mut c_board = board.clone();
-      c_board.apply_move(m);
-      c_line.add_move(m);
+  // Searches this thread's share of the root moves, which is why nothing at the root is probed or
+  // stored. The first move gets the full window and proves the rest worse with null windows
+  pub fn search_root(&mut self, board: &mut Board, mut moves: MoveList, depth: u8) -> (f32, Line) {
+    self.seldepth = 0;
+    self.visit(0);
+    self.order_moves(board, &mut moves, None);
+    let (mut alpha, beta) = (-INFINITY, INFINITY);
+    let mut best_score = -INFINITY;
+    let mut best_line = Line::new();
 
-      let mut score;
-      if self.current_depth <= 1 {
-        // First move
-        let pvs = self.pvs(c_board, -beta, -alpha, depth - 1, c_line);
-        score = -pvs.0;
-        c_line = pvs.1;
-      } else {
-        let pvs = self.pvs(c_board, -alpha - 1.0, -alpha, depth - 1, c_line);
-        score = -pvs.0;
-        c_line = pvs.1;
-        if score > alpha && beta - alpha > 1.0 {
-          let pvs = self.pvs(c_board, -beta, -alpha, depth - 1, c_line);
-          score = -pvs.0;
-          c_line = pvs.1;
-        }
-      }
+    for (i, m) in moves.into_iter().enumerate() {
+      self.played[0] = m;
+      let undo = board.make_move(m);
+      let reduction = (i > 0).then_some(0);
+      let score = self.search_child(board, alpha, beta, depth, reduction, 1);
+      board.unmake_move(m, undo);
 
-      if score >= beta {
-        return (beta, c_line);
+      if self.control.stopped() {
+        break;
       }
-      if score > alpha {
-        alpha = score;
-        line = c_line;
+      if score > best_score {
+        best_score = score;
+        best_line.set(m, &self.pv[1]);
+        alpha = alpha.max(score);
       }
     }
 
-    return (alpha, line);
+    (best_score, best_line)
   }
 
-  pub fn alpha_beta_max(
-    &mut self, board: Board, moves: Vec<Move>, mut alpha: f32, beta: f32, depth: u8, line: Line,
-  ) -> (f32, Line) {
+  // Negamax principal variation search, scores are from the side to move's point of view and fail
+  // soft: the returned score can lie outside the window. The line found is left in the PV table at
+  // this ply, empty unless a move raised alpha
+  pub fn pvs(
+    &mut self, board: &mut Board, mut alpha: f32, beta: f32, depth: u8, ply: usize,
+  ) -> f32 {
+    self.pv[ply].clear();
     if depth == 0 {
-      let eval = Self::evaluate(board);
-      return (eval, line);
+      return self.quiesce(board, alpha, beta, ply);
+    }
+
+    // The result is thrown away once stopped, so just unwind without counting the node
+    if self.control.stopped() {
+      return 0.0;
+    }
+    self.visit(ply);
+
+    // Cutting off on the principal variation would cut the reported line short. Null windows are
+    // NULL_WINDOW wide, give or take float rounding
+    let pv_node = beta - alpha > 1.5 * Self::NULL_WINDOW;
+    let entry = self.tt.probe(board.hash, ply);
+    if let Some(score) = entry
+      .filter(|_| !pv_node)
+      .and_then(|e| Self::tt_cutoff(e, alpha, beta, depth))
+    {
+      return score;
+    }
+
+    // Passing is never allowed twice in a row, in check, where zugzwang is likely or while a null
+    // move cutoff is being verified
+    let in_check = board.in_check();
+    let mut eval = None;
+    let null_allowed = !pv_node
+      && !in_check
+      && depth >= Self::NULL_MIN_DEPTH
+      && ply >= self.null_min_ply
+      && (ply == 0 || self.played[ply - 1] != Move::NULL)
+      && board.has_non_pawn_material(board.turn);
+    if null_allowed {
+      let static_eval = Self::static_eval(board, entry);
+      eval = Some(static_eval);
+      if static_eval >= beta {
+        if let Some(score) = self.null_move(board, beta, depth, static_eval, ply) {
+          return score;
+        }
+      }
+    }
+
+    // Out of check the moves come in stages, the quiets only generated once they're next in line so
+    // a capture that cuts off saves the work. They're next straight away when the table's move is
+    // quiet, or when there are no captures and they're needed to tell if there are any moves at all
+    let mut moves = if in_check {
+      MoveGen::evasions(board)
+    } else {
+      MoveGen::captures(board)
+    };
+    self.score_moves(board, &mut moves, entry);
+    let tt_move = entry.and_then(|e| e.best_move);
+    let mut quiets_pending = !in_check;
+    if quiets_pending && (moves.is_empty() || tt_move.is_some_and(|m| !moves.contains(&m))) {
+      self.add_quiets(board, &mut moves, entry);
+      quiets_pending = false;
+    }
+    if moves.is_empty() {
+      return Self::no_moves_score(board, ply);
     }
 
+    let alpha_orig = alpha;
     let mut best_score = -INFINITY;
-    let mut best_line = line.clone();
-    for m in moves {
-      let mut c_board = board.clone();
-      let mut c_line = line.clone();
+    let mut best_move = None;
+    let mut quiets = MoveList::new();
+
+    for i in 0.. {
+      // Losing captures are ordered after the quiets too, so those are needed before the first one
+      let losing = i < moves.len() && {
+        moves.pick(i);
+        moves.score(i) < 0
+      };
+      if quiets_pending && (i == moves.len() || losing) {
+        self.add_quiets(board, &mut moves, entry);
+        quiets_pending = false;
+      }
+      if i == moves.len() {
+        break;
+      }
+      let m = moves.pick(i);
+      let quiet = !MoveGen::is_tactical(board, m);
+
+      // Quiets come last with the ones that rarely cut off at the back, so near the leaves the late
+      // ones are skipped. Not before a move that avoids getting mated has turned up though
+      if quiet
+        && !pv_node
+        && !in_check
+        && depth <= Self::LMP_MAX_DEPTH
+        && i >= 3 + (depth * depth) as usize
+        && best_score > -(MATE_SCORE - MAX_PLY as f32)
+      {
+        self.stats.late_move_prunes += 1;
+        continue;
+      }
 
-      c_line.add_move(m);
-      c_board.apply_move(m);
+      self.played[ply] = m;
+      let undo = board.make_move(m);
 
-      let res = Self::alpha_beta_min(self, c_board, MoveGen::gen_moves(c_board, c_board.turn, true), alpha, beta, depth - 1, c_line);
-      let score = res.0;
+      let reduction = if i == 0 {
+        None
+      } else if quiet && depth >= Self::LMR_MIN_DEPTH && i >= Self::LMR_MIN_MOVES {
+        let history = self.history[!board.turn as usize][m.start() as usize][m.dest() as usize];
+        Some(self.reduction(depth, i + 1, pv_node, in_check || board.in_check(), history))
+      } else {
+        Some(0)
+      };
+      let score = self.search_child(board, alpha, beta, depth, reduction, ply + 1);
+      board.unmake_move(m, undo);
 
-      if best_score < score {
+      if self.control.stopped() {
+        break;
+      }
+      if score > best_score {
         best_score = score;
+        best_move = Some(m);
         if score > alpha {
           alpha = score;
-          best_line = res.1;
+          let (node, child) = self.pv.split_at_mut(ply + 1);
+          node[ply].set(m, &child[0]);
         }
       }
       if score >= beta {
-        return (score, best_line);
+        if quiet {
+          self.update_history(board.turn, m, depth, &quiets);
+        }
+        break;
+      }
+      if quiet {
+        quiets.push(m);
       }
     }
 
-    (best_score, best_line)
+    let bound = if best_score >= beta {
+      Bound::Lower
+    } else if best_score > alpha_orig {
+      Bound::Exact
+    } else {
+      Bound::Upper
+    };
+    let entry = TTEntry {
+      depth,
+      bound,
+      score: best_score,
+      best_move,
+      eval,
+    };
+    self.store(board, ply, entry);
+    best_score
   }
 
-  pub fn alpha_beta_min(
-    &mut self, board: Board, moves: Vec<Move>, alpha: f32, mut beta: f32, depth: u8, line: Line,
-  ) -> (f32, Line) {
-    // println!("Alpha Min @ alpha = {} beta = {}", alpha, beta);
-    if depth == 0 {
-      let eval = Self::evaluate(board);
-      // println!("Eval Min @ depth = {} line = {} eval = {}", self.current_depth, line, eval);
-      return (eval, line);
+  // Hands the opponent a free move. If a reduced search still fails high the position is good
+  // enough to cut without searching any real move, since some move is nearly always better than
+  // passing
+  fn null_move(
+    &mut self, board: &mut Board, beta: f32, depth: u8, eval: f32, ply: usize,
+  ) -> Option<f32> {
+    // Deeper searches and evals further above beta can afford to look less far
+    let reduction = 3 + depth / 6 + ((eval - beta) / 2.0).min(3.0) as u8;
+
+    self.played[ply] = Move::NULL;
+    let en_passant_square = board.make_null_move();
+    self.stats.null_moves += 1;
+    let score = self.pvs(
+      board,
+      -beta,
+      -beta + Self::NULL_WINDOW,
+      depth.saturating_sub(reduction + 1),
+      ply + 1,
+    );
+    board.unmake_null_move(en_passant_square);
+
+    let score = -score;
+    if score < beta || self.control.stopped() {
+      return None;
+    }
+    // A mate that relies on the opponent passing isn't one
+    let score = if is_mate(score) { beta } else { score };
+    if depth < Self::NULL_VERIFY_DEPTH {
+      self.stats.null_cutoffs += 1;
+      return Some(score);
     }
 
-    self.current_depth += 1;
+    // A wrong cutoff this deep costs a lot, so zugzwang is ruled out with a reduced search of the
+    // real moves that can't pass itself for the next few plies
+    let reduced = depth.saturating_sub(reduction);
+    let null_min_ply = self.null_min_ply;
+    self.null_min_ply = ply + 3 * reduced as usize / 4;
+    let verified = self.pvs(board, beta - Self::NULL_WINDOW, beta, reduced, ply);
+    self.null_min_ply = null_min_ply;
+    // The verification searched this same ply, its line isn't the one the real moves will find
+    self.pv[ply].clear();
+
+    if verified < beta {
+      return None;
+    }
+    self.stats.null_cutoffs += 1;
+    Some(score)
+  }
 
-    let mut best_score = INFINITY;
-    let mut best_line = line.clone();
-    for m in moves {
-      let mut c_board = board.clone();
-      let mut c_line = line.clone();
+  // Searches the move just made, whose position is at the given ply. Only the first move is
+  // expected to be best and gets the full window, passed without a reduction. The others get a null
+  // window around alpha, reduced when they're late quiets. A reduced search that beats alpha is
+  // repeated at full depth, and one that still does at full width
+  fn search_child(
+    &mut self, board: &mut Board, alpha: f32, beta: f32, depth: u8, reduction: Option<u8>,
+    ply: usize,
+  ) -> f32 {
+    if let Some(reduction) = reduction {
+      let null_window = |engine: &mut Self, board: &mut Board, depth: u8| {
+        -engine.pvs(board, -alpha - Self::NULL_WINDOW, -alpha, depth, ply)
+      };
+
+      let mut score = null_window(self, board, depth - 1 - reduction);
+      if reduction > 0 {
+        self.stats.reductions += 1;
+        if score > alpha {
+          self.stats.re_searches += 1;
+          score = null_window(self, board, depth - 1);
+        }
+      }
+      if score <= alpha || score >= beta {
+        return score;
+      }
+    }
 
-      c_board.apply_move(m);
-      c_line.add_move(m);
+    -self.pvs(board, -beta, -alpha, depth - 1, ply)
+  }
 
-      let res: (f32, Line) = Self::alpha_beta_max(self, c_board, MoveGen::gen_moves(c_board, c_board.turn, true), alpha, beta, depth - 1, c_line);
-      let score = res.0;
+  // Grows with the depth left and how late the move comes. Less for moves with a good history, on
+  // the principal variation and around checks, where missing something costs the most. At least one
+  // ply is always left to search
+  fn reduction(
+    &self, depth: u8, move_number: usize, pv_node: bool, check: bool, history: i32,
+  ) -> u8 {
+    let mut reduction = 0.75 + (depth as f32).ln() * (move_number as f32).ln() / 2.25;
+    reduction -= history as f32 / (Self::HISTORY_MAX / 2) as f32;
+    if pv_node {
+      reduction -= 1.0;
+    }
+    if check {
+      reduction -= 1.0;
+    }
+    (reduction.round().max(0.0) as u8).min(depth - 2)
+  }
 
-      if best_score > score {
-        best_score = score;
-        if beta > score {
-          beta = score;
-          best_line = res.1;
-        }
+  // Rewards the quiet move that cut off and penalises the ones tried before it. Each update moves
+  // an entry less the closer it already is to the limit, which keeps it in range and lets old
+  // results fade
+  fn update_history(&mut self, side: Sides, best: Move, depth: u8, tried: &MoveList) {
+    let bonus = (depth as i32 * depth as i32).min(Self::HISTORY_MAX / 4);
+    let history = &mut self.history[side as usize];
+
+    let mut update = |m: Move, bonus: i32| {
+      let entry = &mut history[m.start() as usize][m.dest() as usize];
+      *entry += bonus - *entry * bonus.abs() / Self::HISTORY_MAX;
+    };
+    update(best, bonus);
+    for m in tried {
+      update(*m, -bonus);
+    }
+  }
+
+  // Results of a stopped search are unreliable and stay out of the table
+  fn store(&self, board: &Board, ply: usize, entry: TTEntry) {
+    if !self.control.stopped() {
+      self.tt.store(board.hash, ply, entry);
+    }
+  }
+
+  // A lower bound cuts off at or above beta and an upper one at or below alpha
+  fn tt_cutoff(entry: TTEntry, alpha: f32, beta: f32, depth: u8) -> Option<f32> {
+    let usable = match entry.bound {
+      Bound::Exact => true,
+      Bound::Lower => entry.score >= beta,
+      Bound::Upper => entry.score <= alpha,
+    };
+    (entry.depth >= depth && usable).then_some(entry.score)
+  }
+
+  // From the side to move's point of view. Evaluating generates every move for both sides, so a
+  // static eval already in the table is reused
+  fn static_eval(board: &Board, entry: Option<TTEntry>) -> f32 {
+    entry.and_then(|e| e.eval).unwrap_or_else(|| {
+      let eval = Self::evaluate(board);
+      if board.turn == Sides::WHITE {
+        eval
+      } else {
+        -eval
       }
+    })
+  }
 
-      if score <= alpha {
-        println!("score = {} <= alpha {}", score, alpha);
-        return (score, best_line);
+  fn order_moves(&self, board: &Board, moves: &mut MoveList, entry: Option<TTEntry>) {
+    self.score_moves(board, moves, entry);
+    moves.sort();
+  }
+
+  // The table's best move first, then captures that don't lose material by how much they win,
+  // quiets by history and captures that do lose material last
+  fn score_moves(&self, board: &Board, moves: &mut MoveList, entry: Option<TTEntry>) {
+    let tt_move = entry.and_then(|e| e.best_move);
+    let history = &self.history[board.turn as usize];
+
+    moves.score_moves(|m| {
+      if Some(m) == tt_move {
+        i32::MAX
+      } else if MoveGen::is_tactical(board, m) {
+        let gain = (see(board, m) * 100.0) as i32;
+        gain
+          + if gain >= 0 {
+            Self::CAPTURE_SCORE
+          } else {
+            -Self::CAPTURE_SCORE
+          }
+      } else {
+        history[m.start() as usize][m.dest() as usize]
       }
-    }
-    return (best_score, best_line);
+    });
   }
 
-  fn quiesce(board: Board, mut alpha: f32, beta: f32) -> f32 {
-    let eval = Self::evaluate(board);
-    if eval >= beta {
-      return beta;
-    }
+  // The quiet stage, scored and added behind the captures
+  fn add_quiets(&self, board: &Board, moves: &mut MoveList, entry: Option<TTEntry>) {
+    let mut quiets = MoveGen::quiets(board);
+    self.score_moves(board, &mut quiets, entry);
+    moves.extend(&quiets);
+  }
 
-    if alpha < eval {
-      alpha = eval;
+  // Plays out captures until the position is quiet so the search doesn't stop in the middle of an
+  // exchange. In check every evasion is searched instead, standing pat isn't an option there
+  fn quiesce(&mut self, board: &mut Board, mut alpha: f32, beta: f32, ply: usize) -> f32 {
+    if self.control.stopped() {
+      return 0.0;
     }
+    self.visit(ply);
 
-    for m in MoveGen::gen_moves(board, board.turn, true) {
-      if m.capture != None {
-        let mut c_board = board.clone();
-        c_board.apply_move(m);
+    let entry = self.tt.probe(board.hash, ply);
+    if let Some(score) = entry.and_then(|e| Self::tt_cutoff(e, alpha, beta, 0)) {
+      return score;
+    }
 
-        let score = -Self::quiesce(c_board, -beta, -alpha);
+    let in_check = board.in_check();
+    let eval = (!in_check).then(|| Self::static_eval(board, entry));
+    let alpha_orig = alpha;
+    let mut best_score = -INFINITY;
+    let mut best_move = None;
 
-        if score >= beta {
-          return beta;
-        }
-        if score > alpha {
-          alpha = score;
+    let mut moves = match eval {
+      Some(eval) => {
+        if eval >= beta {
+          return eval;
         }
+        alpha = alpha.max(eval);
+        best_score = eval;
+
+        // Best exchanges first, in centipawns so the scores sort as integers
+        let mut captures = MoveGen::captures(board);
+        captures.score_moves(|m| (see(board, m) * 100.0) as i32);
+        captures
+      }
+      None => MoveGen::evasions(board),
+    };
+
+    if in_check && moves.is_empty() {
+      return Self::no_moves_score(board, ply);
+    }
+
+    for i in 0..moves.len() {
+      let m = moves.pick(i);
+      // Losing captures can't raise alpha once the opponent recaptures, and the rest are worse
+      if !in_check && moves.score(i) < 0 {
+        break;
+      }
+
+      let undo = board.make_move(m);
+      let score = -self.quiesce(board, -beta, -alpha, ply + 1);
+      board.unmake_move(m, undo);
+
+      if self.control.stopped() {
+        break;
+      }
+      if score > best_score {
+        best_score = score;
+        best_move = Some(m);
+        alpha = alpha.max(score);
+      }
+      if score >= beta {
+        break;
       }
     }
 
-    return alpha;
+    let bound = if best_score >= beta {
+      Bound::Lower
+    } else if best_score > alpha_orig {
+      Bound::Exact
+    } else {
+      Bound::Upper
+    };
+    let entry = TTEntry {
+      depth: 0,
+      bound,
+      score: best_score,
+      best_move: best_move.or(entry.and_then(|e| e.best_move)),
+      eval,
+    };
+    self.store(board, ply, entry);
+    best_score
   }
 }
@@ -1 +1,4 @@
 pub mod engine;
+pub mod search;
+pub mod see;
+pub mod tt;
@@ -0,0 +1,179 @@
+use std::{
+  fmt,
+  ops::AddAssign,
+  sync::atomic::{AtomicBool, AtomicU64, Ordering},
+  thread,
+  time::{Duration, Instant},
+};
+
+use crate::structs::Line;
+
+pub const MAX_DEPTH: u8 = 64;
+pub const MAX_PLY: usize = 128;
+
+// Same units as Engine::evaluate, far above any material balance while still fitting the
+// transposition table's centipawns. Mates are scored MATE_SCORE minus the plies to the mate so
+// shorter ones are preferred
+pub const MATE_SCORE: f32 = 300.0;
+
+pub fn is_mate(score: f32) -> bool {
+  score.abs() >= MATE_SCORE - MAX_PLY as f32
+}
+
+// Shared by every thread of one search. The engines add their node counts in batches and check the
+// limits while doing so, the first to hit one raises the stop flag for the rest. The GUI's stop
+// raises it too. It's only obeyed once armed, after depth 1, so there is always a searched move
+pub struct SearchControl {
+  stop: AtomicBool,
+  armed: AtomicBool,
+  nodes: AtomicU64,
+  deadline: Option<Instant>,
+  node_limit: Option<u64>,
+}
+
+impl SearchControl {
+  pub fn new(deadline: Option<Instant>, node_limit: Option<u64>) -> Self {
+    Self {
+      stop: AtomicBool::new(false),
+      armed: AtomicBool::new(false),
+      nodes: AtomicU64::new(0),
+      deadline,
+      node_limit,
+    }
+  }
+
+  // Counts without checking the limits, for nodes of a search that has already finished
+  pub fn record_nodes(&self, nodes: u64) {
+    self.nodes.fetch_add(nodes, Ordering::Relaxed);
+  }
+
+  pub fn add_nodes(&self, nodes: u64) {
+    let total = self.nodes.fetch_add(nodes, Ordering::Relaxed) + nodes;
+    if self.node_limit.is_some_and(|limit| total >= limit)
+      || self
+        .deadline
+        .is_some_and(|deadline| Instant::now() >= deadline)
+    {
+      self.stop.store(true, Ordering::Relaxed);
+    }
+  }
+
+  // How many nodes an engine may count before handing them over. Near the node limit that's every
+  // node, so however many threads are searching the limit is kept to within a node or so each
+  pub fn batch_size(&self, batch: u64) -> u64 {
+    match self.node_limit {
+      Some(limit) if limit.saturating_sub(self.nodes()) < 64 * batch => 1,
+      _ => batch,
+    }
+  }
+
+  pub fn nodes(&self) -> u64 {
+    self.nodes.load(Ordering::Relaxed)
+  }
+
+  pub fn node_limit_reached(&self) -> bool {
+    self.node_limit.is_some_and(|limit| self.nodes() >= limit)
+  }
+
+  pub fn stop(&self) {
+    self.stop.store(true, Ordering::Relaxed);
+  }
+
+  pub fn arm(&self) {
+    self.armed.store(true, Ordering::Relaxed);
+  }
+
+  pub fn stopped(&self) -> bool {
+    self.armed.load(Ordering::Relaxed) && self.stop.load(Ordering::Relaxed)
+  }
+
+  // Infinite and ponder searches hold their bestmove back until the GUI says stop
+  pub fn wait_for_stop(&self) {
+    while !self.stop.load(Ordering::Relaxed) {
+      thread::sleep(Duration::from_millis(1));
+    }
+  }
+}
+
+impl Default for SearchControl {
+  fn default() -> Self {
+    Self::new(None, None)
+  }
+}
+
+// One `info` line for the GUI, sent after every completed iteration
+pub struct SearchInfo<'a> {
+  pub depth: u8,
+  pub seldepth: u8,
+  // From the side to move's point of view
+  pub score: f32,
+  pub nodes: u64,
+  pub hashfull: u16,
+  pub time: Duration,
+  pub pv: &'a Line,
+}
+
+impl fmt::Display for SearchInfo<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "info depth {} seldepth {} ", self.depth, self.seldepth)?;
+
+    if is_mate(self.score) {
+      // Plies to mate become moves, negative when the side to move is the one getting mated
+      let plies = (MATE_SCORE - self.score.abs()).round() as i32;
+      let moves = (plies + 1) / 2;
+      write!(
+        f,
+        "score mate {} ",
+        if self.score > 0.0 { moves } else { -moves }
+      )?;
+    } else {
+      write!(f, "score cp {} ", (self.score * 100.0).round() as i32)?;
+    }
+
+    let millis = self.time.as_millis();
+    write!(
+      f,
+      "nodes {} nps {} hashfull {} time {} pv",
+      self.nodes,
+      (self.nodes as f64 / self.time.as_secs_f64().max(1e-3)) as u64,
+      self.hashfull,
+      millis
+    )?;
+
+    for m in self.pv.moves() {
+      write!(f, " {}", m)?;
+    }
+    Ok(())
+  }
+}
+
+// How often the selective parts of the search fired, reported once a search is done
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SearchStats {
+  pub null_moves: u64,
+  pub null_cutoffs: u64,
+  // Late moves searched to a reduced depth, and how many of those had to be searched again
+  pub reductions: u64,
+  pub re_searches: u64,
+  pub late_move_prunes: u64,
+}
+
+impl AddAssign for SearchStats {
+  fn add_assign(&mut self, other: Self) {
+    self.null_moves += other.null_moves;
+    self.null_cutoffs += other.null_cutoffs;
+    self.reductions += other.reductions;
+    self.re_searches += other.re_searches;
+    self.late_move_prunes += other.late_move_prunes;
+  }
+}
+
+impl fmt::Display for SearchStats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "info string null moves {} cutoffs {} reductions {} re-searches {} late move prunes {}",
+      self.null_moves, self.null_cutoffs, self.reductions, self.re_searches, self.late_move_prunes
+    )
+  }
+}
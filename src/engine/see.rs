@@ -0,0 +1,90 @@
+use crate::structs::{BitBoard, Board, Move, Pieces, Sides};
+
+// Static exchange evaluation: the material the moving side comes out with if both sides keep
+// recapturing on the destination square with their least valuable piece, either side being free to
+// stop when carrying on would lose more. Sliders behind the pieces that have already captured join
+// in as the pieces in front of them leave. Pins are ignored
+
+// Same units as Engine::evaluate, indexed by Pieces
+pub const SEE_VALUES: [f32; 6] = [1.0, 3.0, 3.0, 5.0, 9.0, 100.0];
+
+fn value(piece: Pieces) -> f32 {
+  SEE_VALUES[piece as usize]
+}
+
+pub fn see(board: &Board, m: Move) -> f32 {
+  let (start, dest) = (m.start(), m.dest());
+  let Some((side, moved)) = board.piece_at(start) else {
+    return 0.0;
+  };
+
+  if m.is_castle() {
+    return 0.0;
+  }
+
+  let mut occupied = board.occupied() ^ BitBoard::from_pos(start);
+  let mut gains = [0.0; 32];
+
+  gains[0] = if m.is_en_passant() {
+    let captured = if side == Sides::WHITE {
+      dest - 8
+    } else {
+      dest + 8
+    };
+    occupied = occupied ^ BitBoard::from_pos(captured);
+    value(Pieces::PAWN)
+  } else {
+    board.captured_piece(m).map_or(0.0, value)
+  };
+
+  // The piece now standing on the square, the next one to be taken
+  let mut on_square = moved;
+  if let Some(promotion) = m.promotion() {
+    gains[0] += value(promotion) - value(Pieces::PAWN);
+    on_square = promotion;
+  }
+
+  let mut depth = 0;
+  let mut turn = !side;
+
+  loop {
+    let attackers = board.attackers_to(dest, occupied) & occupied;
+    let ours = attackers & board.bb_sides[turn as usize];
+    if ours == BitBoard(0) {
+      break;
+    }
+
+    // The king can only recapture when nothing is left to take it back
+    let Some(piece) = [
+      Pieces::PAWN,
+      Pieces::KNIGHT,
+      Pieces::BISHOP,
+      Pieces::ROOK,
+      Pieces::QUEEN,
+      Pieces::KING,
+    ]
+    .into_iter()
+    .find(|p| ours & board.bb_pieces[turn as usize][*p as usize] != BitBoard(0)) else {
+      break;
+    };
+
+    if piece == Pieces::KING && attackers & board.bb_sides[!turn as usize] != BitBoard(0) {
+      break;
+    }
+
+    depth += 1;
+    gains[depth] = value(on_square) - gains[depth - 1];
+
+    let attacker = ours & board.bb_pieces[turn as usize][piece as usize];
+    occupied = occupied ^ BitBoard(attacker.0 & attacker.0.wrapping_neg());
+    on_square = piece;
+    turn = !turn;
+  }
+
+  // Walk back up the sequence, each side taking the better of recapturing or standing pat
+  while depth > 0 {
+    gains[depth - 1] = -(-gains[depth - 1]).max(gains[depth]);
+    depth -= 1;
+  }
+  gains[0]
+}
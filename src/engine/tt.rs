@@ -0,0 +1,223 @@
+use std::{
+  mem::size_of,
+  sync::atomic::{AtomicU64, AtomicU8, Ordering},
+};
+
+use super::search::{MATE_SCORE, MAX_PLY};
+use crate::structs::Move;
+
+// Results of positions already searched, shared by every search thread. Each slot holds the key
+// xored with the data, so a slot torn by two threads writing at once fails verification instead of
+// handing back another position's data, and no locking is needed
+
+pub const DEFAULT_HASH_MB: usize = 16;
+pub const MAX_HASH_MB: usize = 4096;
+
+const BUCKET_SIZE: usize = 4;
+// Ages wrap at 6 bits, the space left in the packed data
+const AGE_MASK: u8 = 0x3f;
+const NO_EVAL: i16 = i16::MIN;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+  Exact = 1,
+  // The score is at least this, the search failed high
+  Lower = 2,
+  // The score is at most this, no move raised alpha
+  Upper = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TTEntry {
+  pub depth: u8,
+  pub bound: Bound,
+  pub score: f32,
+  pub best_move: Option<Move>,
+  pub eval: Option<f32>,
+}
+
+// Packed into 64 bits: move in bits 0-15, score and static eval as centipawns in 16-31 and 32-47,
+// depth in 48-55, bound in 56-57 and age in 58-63. Empty slots are all zeros, bound is never 0
+impl TTEntry {
+  fn pack(&self, age: u8) -> u64 {
+    let eval = self.eval.map_or(NO_EVAL, to_centipawns);
+    self.best_move.map_or(0, |m| m.0) as u64
+      | (to_centipawns(self.score) as u16 as u64) << 16
+      | (eval as u16 as u64) << 32
+      | (self.depth as u64) << 48
+      | (self.bound as u64) << 56
+      | ((age & AGE_MASK) as u64) << 58
+  }
+
+  fn unpack(data: u64) -> Option<(Self, u8)> {
+    let bound = match (data >> 56) & 3 {
+      1 => Bound::Exact,
+      2 => Bound::Lower,
+      3 => Bound::Upper,
+      _ => return None,
+    };
+    let eval = (data >> 32) as u16 as i16;
+
+    let entry = Self {
+      depth: (data >> 48) as u8,
+      bound,
+      score: (data >> 16) as u16 as i16 as f32 / 100.0,
+      best_move: Some(Move(data as u16)).filter(|m| m.0 != 0),
+      eval: (eval != NO_EVAL).then_some(eval as f32 / 100.0),
+    };
+    Some((entry, (data >> 58) as u8))
+  }
+}
+
+fn to_centipawns(score: f32) -> i16 {
+  (score * 100.0)
+    .round()
+    .clamp(NO_EVAL as f32 + 1.0, i16::MAX as f32) as i16
+}
+
+// Mate scores count plies from the root, but a position can be reached at any ply. They're stored
+// counting from the position itself and turned back on the way out
+fn score_to_tt(score: f32, ply: usize) -> f32 {
+  if score >= MATE_SCORE - MAX_PLY as f32 {
+    score + ply as f32
+  } else if score <= -(MATE_SCORE - MAX_PLY as f32) {
+    score - ply as f32
+  } else {
+    score
+  }
+}
+
+fn score_from_tt(score: f32, ply: usize) -> f32 {
+  if score >= MATE_SCORE - MAX_PLY as f32 {
+    score - ply as f32
+  } else if score <= -(MATE_SCORE - MAX_PLY as f32) {
+    score + ply as f32
+  } else {
+    score
+  }
+}
+
+#[derive(Default)]
+struct Slot {
+  key: AtomicU64,
+  data: AtomicU64,
+}
+
+impl Slot {
+  fn load(&self) -> (u64, u64) {
+    let data = self.data.load(Ordering::Relaxed);
+    (self.key.load(Ordering::Relaxed) ^ data, data)
+  }
+}
+
+// One cache line, so a probe touches memory once
+#[derive(Default)]
+#[repr(align(64))]
+struct Bucket {
+  slots: [Slot; BUCKET_SIZE],
+}
+
+pub struct TranspositionTable {
+  buckets: Vec<Bucket>,
+  // Bumped every search so entries left over from earlier ones are replaced first
+  age: AtomicU8,
+}
+
+impl TranspositionTable {
+  pub fn new(mb: usize) -> Self {
+    let size = (mb * 1024 * 1024 / size_of::<Bucket>()).max(1);
+    Self {
+      buckets: (0..size).map(|_| Bucket::default()).collect(),
+      age: AtomicU8::new(0),
+    }
+  }
+
+  pub fn clear(&self) {
+    for slot in self.buckets.iter().flat_map(|b| &b.slots) {
+      slot.key.store(0, Ordering::Relaxed);
+      slot.data.store(0, Ordering::Relaxed);
+    }
+    self.age.store(0, Ordering::Relaxed);
+  }
+
+  pub fn new_search(&self) {
+    let age = (self.age() + 1) & AGE_MASK;
+    self.age.store(age, Ordering::Relaxed);
+  }
+
+  fn age(&self) -> u8 {
+    self.age.load(Ordering::Relaxed)
+  }
+
+  fn bucket(&self, hash: u64) -> &Bucket {
+    // The high bits pick the bucket, spreading hashes evenly whatever the table's size
+    let index = ((hash as u128 * self.buckets.len() as u128) >> 64) as usize;
+    &self.buckets[index]
+  }
+
+  pub fn probe(&self, hash: u64, ply: usize) -> Option<TTEntry> {
+    self.bucket(hash).slots.iter().find_map(|slot| {
+      let (key, data) = slot.load();
+      let (mut entry, _) = TTEntry::unpack(data).filter(|_| key == hash)?;
+      entry.score = score_from_tt(entry.score, ply);
+      Some(entry)
+    })
+  }
+
+  pub fn store(&self, hash: u64, ply: usize, mut entry: TTEntry) {
+    let age = self.age();
+    let bucket = self.bucket(hash);
+
+    // The slot already holding this position, else an empty one, else the least useful: shallow
+    // entries from old searches go first
+    let mut replace = 0;
+    let mut lowest = i32::MAX;
+    let mut previous = None;
+    for (i, slot) in bucket.slots.iter().enumerate() {
+      let (key, data) = slot.load();
+      let Some((old, old_age)) = TTEntry::unpack(data) else {
+        if lowest > i32::MIN {
+          (replace, lowest) = (i, i32::MIN);
+        }
+        continue;
+      };
+
+      if key == hash {
+        (replace, previous) = (i, Some((old, old_age)));
+        break;
+      }
+
+      let value = old.depth as i32 - 8 * (age.wrapping_sub(old_age) & AGE_MASK) as i32;
+      if value < lowest {
+        (replace, lowest) = (i, value);
+      }
+    }
+
+    if let Some((old, old_age)) = previous {
+      // A deeper result from this search is worth more than a shallow bound
+      if entry.bound != Bound::Exact && old_age == age && entry.depth + 2 < old.depth {
+        return;
+      }
+      entry.best_move = entry.best_move.or(old.best_move);
+      entry.eval = entry.eval.or(old.eval);
+    }
+
+    entry.score = score_to_tt(entry.score, ply);
+    let data = entry.pack(age);
+    let slot = &bucket.slots[replace];
+    slot.key.store(hash ^ data, Ordering::Relaxed);
+    slot.data.store(data, Ordering::Relaxed);
+  }
+
+  // Permille of a sample of slots filled during the current search, as UCI's hashfull wants it
+  pub fn hashfull(&self) -> u16 {
+    let age = self.age();
+    let sample = &self.buckets[..self.buckets.len().min(1000 / BUCKET_SIZE)];
+    let used = sample
+      .iter()
+      .flat_map(|b| &b.slots)
+      .filter(|slot| TTEntry::unpack(slot.load().1).is_some_and(|(_, a)| a == age))
+      .count();
+    (used * 1000 / (sample.len() * BUCKET_SIZE)) as u16
+  }
+}
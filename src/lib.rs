@@ -40,11 +40,16 @@ pub fn pos_to_alph(pos: u8) -> Result<String, Error> {
 }
 
 pub fn alph_to_pos(alph: &str) -> Result<u8, Error> {
+  if alph.len() != 2 || !alph.is_ascii() {
+    println!("{} is not a square", alph);
+    return Err(Error);
+  }
+
   let (f, r) = alph.split_at(1);
   let rank = match r.parse::<u8>() {
-    Ok(r) => r - 1,
-    Err(_) => {
-      println!("Failed to convert to pos");
+    Ok(r @ 1..=8) => r - 1,
+    _ => {
+      println!("{} is not a valid rank!", r);
       return Err(Error);
     }
   };
@@ -1,12 +1,13 @@
 use engine::engine::Engine;
+use engine::tt::{DEFAULT_HASH_MB, MAX_HASH_MB};
+use movegen::{magic::SlidingAttacks, perft::PerftOptions};
 use parsers::{
   position::Position,
   time::{Constraints, Time},
 };
-use std::process::exit;
-use structs::{print_bitboard, Board};
+use std::{process::exit, time::Instant};
+use structs::{game::GameState, print_bitboard, Board};
 use threading::ThreadPool;
-use Iridium::pos_to_alph;
 
 mod engine;
 mod lib;
@@ -20,13 +21,84 @@ fn ucimode() {
   println!("id name {} (commit: {})", lib::NAME, env!("GIT_HASH"));
   println!("id author {}", lib::AUTHOR);
   // Options
+  println!(
+    "option name Hash type spin default {} min 1 max {}",
+    DEFAULT_HASH_MB, MAX_HASH_MB
+  );
+  println!("option name Clear Hash type button");
 
   // Ready
   println!("uciok");
 }
 
+// setoption name <id> [value <x>], option names can have spaces
+fn setoption(thread_pool: &mut ThreadPool, args: &[&str]) {
+  let value_at = args.iter().position(|a| *a == "value");
+  let name = args[..value_at.unwrap_or(args.len())]
+    .iter()
+    .skip_while(|a| **a == "name")
+    .copied()
+    .collect::<Vec<&str>>()
+    .join(" ");
+  let value = value_at.map(|i| args[i + 1..].join(" "));
+
+  match (name.to_lowercase().as_str(), value) {
+    ("hash", Some(mb)) => match mb.parse() {
+      Ok(mb) => thread_pool.resize_hash(mb),
+      Err(_) => println!("Error invalid hash size: {}", mb),
+    },
+    ("clear hash", _) => thread_pool.clear_hash(),
+    _ => println!("Error unknown option: {}", name),
+  }
+}
+
+// perft <depth> [threads <n>] [hash <mb>], prints the nodes under each move then the total
+fn perft(board: Board, args: &[&str]) {
+  let depth: u8 = match args.first().and_then(|d| d.parse().ok()) {
+    Some(d) => d,
+    None => {
+      println!("Error perft needs a depth");
+      return;
+    }
+  };
+
+  let mut options = PerftOptions::default();
+  for (i, arg) in args.iter().enumerate() {
+    let value = args.get(i + 1).and_then(|v| v.parse().ok());
+    match (*arg, value) {
+      ("threads", Some(n)) => options.threads = n,
+      ("hash", Some(mb)) => options.hash_mb = mb,
+      _ => {}
+    }
+  }
+
+  let start = Instant::now();
+  let divide = board.divide_with(depth, options);
+  let elapsed = start.elapsed();
+
+  for (m, nodes) in &divide {
+    println!("{}: {}", m, nodes);
+  }
+
+  // At depth 0 there are no moves to divide by, the root itself is the one node
+  let nodes: u64 = match depth {
+    0 => 1,
+    _ => divide.iter().map(|(_, n)| n).sum(),
+  };
+  println!();
+  println!("Nodes searched: {}", nodes);
+  println!(
+    "info string time {} ms nps {}",
+    elapsed.as_millis(),
+    (nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+  );
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-  let mut board: Board = Board::default();
+  // Build the sliding attack tables now rather than during the first search
+  SlidingAttacks::get();
+
+  let mut game: GameState = GameState::new(Board::default());
   let mut constraints: Constraints;
 
   let mut thread_pool = ThreadPool::new(25);
@@ -40,39 +112,36 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
       "isready" => {
         println!("readyok")
       }
-      "setoption" => {}
+      "setoption" => setoption(&mut thread_pool, &args[1..]),
       "register" => {}
-      "ucinewgame" => {}
-      "position" => match Position::parse_position(&args) {
-        Ok(b) => board = b,
-        Err(e) => {
-          println!("{}", e);
-          continue;
+      "ucinewgame" => thread_pool.clear_hash(),
+      // The parser prints what went wrong itself
+      "position" => {
+        if let Ok(g) = Position::parse_game(&args) {
+          game = g;
         }
-      },
+      }
+      "perft" => perft(game.board, &args[1..]),
+      "go" if args.get(1) == Some(&"perft") => perft(game.board, &args[2..]),
       "go" => {
         constraints = Time::parse_time(&args);
-        // let moves = MoveGen::gen_moves(board, board.turn, true);
-
-        // println!("possible moves {}", moves.len());
-        let best_move = match thread_pool.search(board, board.turn, 2) {
-          Some(m) => m,
-          None => continue,
-        };
-        println!("{:?} {}", board.turn, board.full_moves);
-        board.apply_move(best_move.0);
-        println!("info score cp {}", Engine::evaluate(board) * 100.0);
-        println!("info {}", best_move.1);
-        println!(
-          "bestmove {}{}",
-          pos_to_alph(best_move.0.start)?,
-          pos_to_alph(best_move.0.dest)?
-        );
+        let board = game.board;
+
+        // The GUI still expects a bestmove when there is nothing left to play
+        if let Some(outcome) = game.outcome().filter(|o| !o.is_claimable()) {
+          println!("info string {}", outcome);
+          println!("bestmove 0000");
+          continue;
+        }
+
+        thread_pool.go(board, constraints);
       }
-      "stop" => {}
+      "stop" => thread_pool.stop(),
       "ponder" => {}
-      "ponderhint" => {}
+      // Pondering has no time limit of its own, so the move found so far is played straight away
+      "ponderhit" => thread_pool.stop(),
       "pb" => {
+        let board = game.board;
         println!();
         print_bitboard(board.get_sides()[0] | board.get_sides()[1]);
         println!();
@@ -81,10 +150,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         print_bitboard(board.get_sides()[1])
       }
       "test" => {
-        let eval = Engine::evaluate(board);
+        let eval = Engine::evaluate(&game.board);
         println!("{}", eval);
       }
-      "quit" => exit(0),
+      "quit" => {
+        thread_pool.stop();
+        exit(0)
+      }
       _ => println!("Error unknown command: {}", command),
     }
   }
@@ -92,11 +164,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 #[cfg(test)]
 mod tests {
-  use engine::engine::Engine;
+  use engine::{
+    engine::Engine,
+    search::{is_mate, SearchControl, SearchInfo, MATE_SCORE},
+    see::see,
+    tt::{Bound, TTEntry, TranspositionTable},
+  };
   use lib::{alph_to_pos, pos_to_alph};
-  use movegen::movegen::MoveGen;
+  use movegen::{
+    attacks::{KING_ATTACKS, KNIGHT_ATTACKS, PAWN_ATTACKS},
+    magic::{BISHOP_DIRECTIONS, ROOK_DIRECTIONS},
+    movegen::MoveGen,
+    movelist::MoveList,
+    perft::PerftOptions,
+  };
   use parsers::{fen::Fen, time::TimerKeeper};
-  use structs::Sides;
+  use rand::{rngs::StdRng, Rng, SeedableRng};
+  use std::{f32::INFINITY, sync::Arc, time::Duration};
+  use structs::{game::Outcome, validate::BoardError, BitBoard, Line, Move, Pieces, Sides};
   use Iridium::bitcount;
 
   use super::*;
@@ -107,7 +192,7 @@ mod tests {
       .split(" ")
       .collect();
 
-    let result = Engine::evaluate(Fen::from_fen(&test_fen).unwrap());
+    let result = Engine::evaluate(&Fen::from_fen(&test_fen).unwrap());
     assert_eq!(result, 0.0);
   }
 
@@ -117,8 +202,799 @@ mod tests {
       .split(" ")
       .collect();
 
-    let result = MoveGen::gen_moves(Fen::from_fen(&test_fen).unwrap(), Sides::BLACK, true);
-    assert_eq!(result, vec![]);
+    let result = MoveGen::gen_moves(&Fen::from_fen(&test_fen).unwrap(), Sides::BLACK, true);
+    assert_eq!(result.len(), 20);
+  }
+
+  // Node counts from https://www.chessprogramming.org/Perft_Results
+  const PERFT_SUITE: [(&str, &[u64]); 6] = [
+    (
+      "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+      &[20, 400, 8902, 197281],
+    ),
+    (
+      "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+      &[48, 2039, 97862],
+    ),
+    (
+      "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+      &[14, 191, 2812, 43238],
+    ),
+    (
+      "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+      &[6, 264, 9467],
+    ),
+    (
+      "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+      &[44, 1486, 62379],
+    ),
+    (
+      "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+      &[46, 2079, 89890],
+    ),
+  ];
+
+  #[test]
+  fn test_perft() {
+    for (fen, counts) in PERFT_SUITE {
+      let mut board = Fen::from_fen(&fen.split(" ").collect::<Vec<&str>>()).unwrap();
+
+      for (depth, nodes) in counts.iter().enumerate() {
+        assert_eq!(
+          board.perft(depth as u8 + 1),
+          *nodes,
+          "{} depth {}",
+          fen,
+          depth + 1
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn test_perft_options() {
+    let options = PerftOptions {
+      threads: 4,
+      hash_mb: 1,
+    };
+
+    for (fen, counts) in PERFT_SUITE {
+      let board = Fen::from_fen(&fen.split(" ").collect::<Vec<&str>>()).unwrap();
+      let depth = counts.len().min(3);
+      assert_eq!(
+        board.perft_with(depth as u8, options),
+        counts[depth - 1],
+        "{}",
+        fen
+      );
+    }
+
+    let board = Board::default();
+    let divide = board.divide(2);
+    assert_eq!(divide.len(), 20);
+    assert!(divide.iter().all(|(_, nodes)| *nodes == 20));
+    assert!(board.divide(0).is_empty());
+    assert_eq!(board.perft_with(0, options), 1);
+  }
+
+  #[test]
+  fn test_promotion() {
+    let test_fen: Vec<&str> = "3r4/4P3/8/8/8/8/k7/4K3 w - - 0 1".split(" ").collect();
+    let board = Fen::from_fen(&test_fen).unwrap();
+
+    let promotions: Vec<Move> = MoveGen::gen_moves(&board, Sides::WHITE, true)
+      .into_iter()
+      .filter(|m| m.promotion().is_some())
+      .collect();
+    assert_eq!(promotions.len(), 8);
+    assert_eq!(
+      promotions
+        .iter()
+        .filter(|m| board.captured_piece(**m) == Some(Pieces::ROOK))
+        .count(),
+      4
+    );
+
+    let mut tboard = board;
+    tboard.apply_move(Move::new_promotion(52, 59, Pieces::KNIGHT));
+    assert_eq!(tboard.bb_pieces[0][Pieces::PAWN as usize], BitBoard(0));
+    assert_eq!(
+      tboard.bb_pieces[0][Pieces::KNIGHT as usize],
+      BitBoard::from_pos(59)
+    );
+    assert_eq!(tboard.bb_pieces[1][Pieces::ROOK as usize], BitBoard(0));
+
+    let position: Vec<&str> = "position fen 3r4/4P3/8/8/8/8/k7/4K3 w - - 0 1 moves e7e8q"
+      .split(" ")
+      .collect();
+    let pboard = Position::parse_game(&position).unwrap().board;
+    assert_eq!(
+      pboard.bb_pieces[0][Pieces::QUEEN as usize],
+      BitBoard::from_pos(60)
+    );
+    assert_eq!(format!("{}", promotions[0]), "e7e8q");
+  }
+
+  #[test]
+  fn test_castling() {
+    let castles = |fen: &str| -> Vec<String> {
+      let fen: Vec<&str> = fen.split(" ").collect();
+      let board = Fen::from_fen(&fen).unwrap();
+      let side = board.turn;
+      MoveGen::gen_moves(&board, side, true)
+        .into_iter()
+        .filter(|m| m.is_castle())
+        .map(|m| m.to_string())
+        .collect()
+    };
+
+    assert_eq!(
+      castles("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1"),
+      vec!["e1g1", "e1c1"]
+    );
+    assert_eq!(castles("r3k2r/8/8/8/8/8/8/R3K2R w Qk - 0 1"), vec!["e1c1"]);
+    // f1 is attacked, b1 being attacked doesn't matter for the queen side
+    assert_eq!(castles("1r2kr2/8/8/8/8/8/8/R3K2R w KQ - 0 1"), vec!["e1c1"]);
+    // Can't castle out of check
+    assert_eq!(
+      castles("r3k2r/4r3/8/8/8/8/8/R3K2R w KQkq - 0 1"),
+      Vec::<String>::new()
+    );
+    assert_eq!(
+      castles("r3k2r/8/8/8/8/8/8/RN2K1NR w KQkq - 0 1"),
+      Vec::<String>::new()
+    );
+
+    let position: Vec<&str> = "position fen r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1 moves e1g1 a8a1"
+      .split(" ")
+      .collect();
+    let board = Position::parse_game(&position).unwrap().board;
+    assert_eq!(
+      board.bb_pieces[0][Pieces::KING as usize],
+      BitBoard::from_pos(6)
+    );
+    assert_eq!(
+      board.bb_pieces[0][Pieces::ROOK as usize],
+      BitBoard::from_pos(5)
+    );
+    assert!(!board.white_can_oo && !board.white_can_ooo);
+    assert!(board.black_can_oo && !board.black_can_ooo);
+  }
+
+  #[test]
+  fn test_en_passant() {
+    let position: Vec<&str> = "position fen 4k3/3p4/8/4P3/8/8/8/4K3 b - - 0 1 moves d7d5"
+      .split(" ")
+      .collect();
+    let board = Position::parse_game(&position).unwrap().board;
+    assert_eq!(board.en_passant_square, Some(43));
+
+    let en_passant: Vec<Move> = MoveGen::gen_moves(&board, Sides::WHITE, true)
+      .into_iter()
+      .filter(|m| m.dest() == 43)
+      .collect();
+    assert_eq!(en_passant.len(), 1);
+
+    let mut tboard = board;
+    tboard.apply_move(en_passant[0]);
+    assert_eq!(tboard.bb_pieces[1][Pieces::PAWN as usize], BitBoard(0));
+    assert_eq!(
+      tboard.bb_pieces[0][Pieces::PAWN as usize],
+      BitBoard::from_pos(43)
+    );
+    assert_eq!(tboard.en_passant_square, None);
+
+    // Taking en passant would clear the fifth rank between the king and the rook
+    let test_fen: Vec<&str> = "8/8/8/KPp4r/8/8/8/7k w - c6 0 1".split(" ").collect();
+    let pinned = Fen::from_fen(&test_fen).unwrap();
+    assert!(MoveGen::gen_moves(&pinned, Sides::WHITE, true)
+      .iter()
+      .all(|m| m.dest() != 42));
+  }
+
+  #[test]
+  fn test_legal_moves() {
+    let moves = |fen: &str| -> Vec<String> {
+      let fen: Vec<&str> = fen.split(" ").collect();
+      let board = Fen::from_fen(&fen).unwrap();
+      let mut moves: Vec<String> = MoveGen::gen_moves(&board, board.turn, true)
+        .iter()
+        .map(|m| m.to_string())
+        .collect();
+      moves.sort();
+      moves
+    };
+
+    // Double check from the knight and the rook, only the king can move
+    assert_eq!(
+      moves("4r2k/8/8/8/8/3n4/Q7/4K3 w - - 0 1"),
+      vec!["e1d1", "e1d2", "e1f1"]
+    );
+    // A single check can be blocked or the checker taken
+    assert_eq!(
+      moves("R3r2k/8/8/8/8/8/3N4/4K3 w - - 0 1"),
+      vec!["a8e8", "d2e4", "e1d1", "e1f1", "e1f2"]
+    );
+    // The pinned rook can only move along the pin, the pinned knight can't move at all
+    assert_eq!(
+      moves("4r2k/8/8/b7/4R3/8/3N4/4K3 w - - 0 1"),
+      vec!["e1d1", "e1e2", "e1f1", "e1f2", "e4e2", "e4e3", "e4e5", "e4e6", "e4e7", "e4e8"]
+    );
+    // The king can't step back along the ray of the rook checking it
+    assert!(!moves("4r2k/8/8/8/8/8/4K3/8 w - - 0 1").contains(&"e2e1".to_string()));
+    // Taking the checking pawn en passant is legal
+    assert!(moves("7k/8/8/2Pp4/2K5/8/8/8 w - d6 0 1").contains(&"c5d6".to_string()));
+  }
+
+  #[test]
+  fn test_gen_types() {
+    let sorted = |moves: &[Move]| {
+      let mut moves = moves.to_vec();
+      moves.sort_by_key(|m| m.0);
+      moves
+    };
+    let mut rng = StdRng::seed_from_u64(0x1D1D);
+
+    for (fen, _) in PERFT_SUITE {
+      let mut board = Fen::from_fen(&fen.split(" ").collect::<Vec<&str>>()).unwrap();
+
+      for _ in 0..40 {
+        let all = MoveGen::gen_moves(&board, board.turn, true);
+        if all.is_empty() {
+          break;
+        }
+
+        let captures = MoveGen::captures(&board);
+        let quiets = MoveGen::quiets(&board);
+        assert!(captures.iter().all(|m| MoveGen::is_tactical(&board, *m)));
+        assert!(quiets.iter().all(|m| !MoveGen::is_tactical(&board, *m)));
+        assert_eq!(sorted(&[&captures[..], &quiets[..]].concat()), sorted(&all));
+
+        let king = board.bb_pieces[board.turn as usize][Pieces::KING as usize];
+        let in_check =
+          MoveGen::is_square_attacked(king.0.trailing_zeros() as u8, &board, !board.turn);
+        let evasions = MoveGen::evasions(&board);
+        assert_eq!(
+          sorted(&evasions),
+          if in_check { sorted(&all) } else { vec![] }
+        );
+
+        board.make_move(all[rng.gen_range(0..all.len())]);
+      }
+    }
+  }
+
+  #[test]
+  fn test_attack_queries() {
+    let board = |fen: &str| Fen::from_fen(&fen.split(" ").collect::<Vec<&str>>()).unwrap();
+    let squares = |names: &[&str]| {
+      BitBoard(
+        names
+          .iter()
+          .fold(0, |bb, name| bb | 1u64 << alph_to_pos(name).unwrap()),
+      )
+    };
+
+    let double_check = board("4r2k/8/8/8/8/3n4/Q7/4K3 w - - 0 1");
+    assert_eq!(double_check.checkers(), squares(&["e8", "d3"]));
+    assert!(double_check.in_check());
+    assert!(!board("4r2k/8/8/b7/4R3/8/3N4/4K3 w - - 0 1").in_check());
+
+    let pins = board("4r2k/8/8/b7/4R3/8/3N4/4K3 w - - 0 1");
+    assert_eq!(pins.pinned(Sides::WHITE), squares(&["e4", "d2"]));
+    assert_eq!(pins.pinned(Sides::BLACK), BitBoard(0));
+
+    let e4 = alph_to_pos("e4").unwrap();
+    assert_eq!(
+      pins.attackers_to(e4, pins.occupied()),
+      squares(&["e8", "d2"])
+    );
+    // With nothing in the way the whole file lines up on e2
+    assert_eq!(
+      pins.attackers_to(alph_to_pos("e2").unwrap(), BitBoard(0)),
+      squares(&["e1", "e4", "e8"])
+    );
+    assert!(pins.is_square_attacked(alph_to_pos("e7").unwrap(), Sides::WHITE));
+    assert!(!pins.is_square_attacked(alph_to_pos("e1").unwrap(), Sides::BLACK));
+
+    // The attack maps agree with asking about every square one at a time
+    for (fen, _) in PERFT_SUITE {
+      let board = board(fen);
+      for side in [Sides::WHITE, Sides::BLACK] {
+        let expected = (0..64)
+          .filter(|s| board.is_square_attacked(*s, side))
+          .fold(BitBoard(0), |bb, s| bb | BitBoard::from_pos(s));
+        assert_eq!(board.attacks(side), expected, "{} {:?}", fen, side);
+      }
+    }
+  }
+
+  #[test]
+  fn test_see() {
+    let see_of = |fen: &str, uci: &str| -> f32 {
+      let board = Fen::from_fen(&fen.split(" ").collect::<Vec<&str>>()).unwrap();
+      see(&board, Move::from_uci(uci, &board).unwrap())
+    };
+
+    // Undefended pawn
+    assert_eq!(
+      see_of("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"),
+      1.0
+    );
+    // Knight takes a pawn and gets taken back, the rest of the pile never joins in
+    assert_eq!(
+      see_of(
+        "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+        "d3e5"
+      ),
+      -2.0
+    );
+    // Queen takes a pawn defended by a pawn
+    assert_eq!(see_of("4k3/8/3p4/4p3/8/8/8/4Q1K1 w - - 0 1", "e1e5"), -8.0);
+    // The second rook x-rays through the first, so the pawn is won
+    assert_eq!(see_of("3rk3/8/8/3p4/8/8/3R4/3R2K1 w - - 0 1", "d2d5"), 1.0);
+    // The king can take back the pawn, unless the rook defends it
+    assert_eq!(see_of("8/8/4k3/3p4/2P5/8/8/4K3 w - - 0 1", "c4d5"), 0.0);
+    assert_eq!(see_of("8/8/4k3/3p4/2P5/8/8/3RK3 w - - 0 1", "c4d5"), 1.0);
+    // En passant, a quiet move and a promotion
+    assert_eq!(see_of("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 1.0);
+    assert_eq!(see_of("4k3/8/8/8/8/8/8/4K1N1 w - - 0 1", "g1f3"), 0.0);
+    assert_eq!(see_of("8/4P3/8/8/8/8/k7/4K3 w - - 0 1", "e7e8q"), 8.0);
+
+    // The queen takes a pawn only to be taken back by another
+    assert_eq!(see_of("4k3/8/3p4/4p3/8/8/8/4Q1K1 w - - 0 1", "e1e5"), -8.0);
+  }
+
+  #[test]
+  fn test_position_moves() {
+    let parse = |position: &str| {
+      let args: Vec<&str> = position.split(" ").collect();
+      Position::parse_game(&args).map(|game| game.board)
+    };
+
+    // Flags come from the legal move, not the squares
+    let board = parse("position startpos moves e2e4 d7d5 e4d5 c7c5 d5c6 b8c6").unwrap();
+    assert_eq!(board.piece_at(42), Some((Sides::BLACK, Pieces::KNIGHT)));
+    assert_eq!(board.bb_pieces[1][Pieces::PAWN as usize].0.count_ones(), 6);
+
+    let board = parse("position fen r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1 moves e1c1 e8g8").unwrap();
+    assert_eq!(board.piece_at(3), Some((Sides::WHITE, Pieces::ROOK)));
+    assert_eq!(board.piece_at(61), Some((Sides::BLACK, Pieces::ROOK)));
+
+    let board = parse("position fen 8/4P3/8/8/8/8/k7/4K3 w - - 0 1 moves e7e8N").unwrap();
+    assert_eq!(board.piece_at(60), Some((Sides::WHITE, Pieces::KNIGHT)));
+
+    // Empty squares, illegal moves, moving into check and junk are all rejected
+    assert!(parse("position startpos moves e3e4").is_err());
+    assert!(parse("position startpos moves e2e4 e7e5 e4e5").is_err());
+    assert!(parse("position startpos moves e2e5").is_err());
+    assert!(parse("position fen 4k3/8/8/8/8/8/r7/4K3 w - - 0 1 moves e1e2").is_err());
+    assert!(parse("position fen 4k3/8/8/8/8/8/8/4KR2 w - - 0 1 moves f1f8 e8e7 e1e2 e7f8").is_ok());
+    assert!(parse("position fen 4k3/8/8/8/8/8/8/4K3 w - - 0 1 moves e1d1 e8f8 d1e3").is_err());
+    assert!(parse("position startpos moves e7e5").is_err());
+    assert!(parse("position startpos moves nonsense").is_err());
+  }
+
+  #[test]
+  fn test_hash() {
+    let parse = |position: &str| -> Board {
+      let args: Vec<&str> = position.split(" ").collect();
+      Position::parse_game(&args).unwrap().board
+    };
+
+    let a = parse("position startpos moves g1f3 g8f6 b1c3 b8c6");
+    let b = parse("position startpos moves b1c3 b8c6 g1f3 g8f6");
+    assert_eq!(a.hash, b.hash);
+    assert_ne!(a.hash, Board::default().hash);
+
+    // Same pieces, different side to move and en passant state
+    let c = parse("position startpos moves e2e4");
+    let d = parse("position startpos moves e2e3 g8f6 e3e4 f6g8");
+    assert_eq!(c.bb_pieces, d.bb_pieces);
+    assert_ne!(c.hash, d.hash);
+
+    let fen: Vec<&str> = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+      .split(" ")
+      .collect();
+    assert_eq!(Fen::from_fen(&fen).unwrap().hash, Board::default().hash);
+  }
+
+  fn perft_copy(board: Board, depth: u8) -> u64 {
+    if depth == 0 {
+      return 1;
+    }
+
+    let mut nodes = 0;
+    for m in MoveGen::gen_moves(&board, board.turn, true) {
+      let mut c_board = board;
+      c_board.apply_move(m);
+      nodes += perft_copy(c_board, depth - 1);
+    }
+    nodes
+  }
+
+  fn perft_unmake(board: &mut Board, depth: u8) -> u64 {
+    if depth == 0 {
+      return 1;
+    }
+
+    let mut nodes = 0;
+    for m in MoveGen::gen_moves(board, board.turn, true) {
+      let before = *board;
+      let undo = board.make_move(m);
+      nodes += perft_unmake(board, depth - 1);
+      board.unmake_move(m, undo);
+      assert_eq!(
+        *board, before,
+        "unmake_move didn't restore the board after {}",
+        m
+      );
+    }
+    nodes
+  }
+
+  #[test]
+  fn test_make_unmake() {
+    for (fen, depth) in [
+      (
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        3,
+      ),
+      (
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        2,
+      ),
+      ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 3),
+      (
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        3,
+      ),
+    ] {
+      let fen: Vec<&str> = fen.split(" ").collect();
+      let mut board = Fen::from_fen(&fen).unwrap();
+      assert_eq!(perft_unmake(&mut board, depth), perft_copy(board, depth));
+    }
+  }
+
+  #[test]
+  fn test_to_fen() {
+    assert_eq!(
+      Board::default().to_fen(),
+      "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+    );
+
+    let mut rng = StdRng::seed_from_u64(0x1D1D);
+    for fen in [
+      "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+      "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+      "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+      "rnbqkb1r/pp1p1ppp/5n2/2pPp3/8/8/PPP1PPPP/RNBQKBNR w KQkq e6 0 4",
+      "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 3 17",
+    ] {
+      let args: Vec<&str> = fen.split(" ").collect();
+      let start = Fen::from_fen(&args).unwrap();
+      assert_eq!(start.to_fen(), fen);
+
+      // Random games from each position, every board along the way has to survive the round trip
+      for _ in 0..20 {
+        let mut board = start;
+        for _ in 0..40 {
+          let side = board.turn;
+          let moves = MoveGen::gen_moves(&board, side, true);
+          if moves.is_empty() {
+            break;
+          }
+          board.apply_move(moves[rng.gen_range(0..moves.len())]);
+
+          let fen = board.to_fen();
+          let args: Vec<&str> = fen.split(" ").collect();
+          assert_eq!(Fen::from_fen(&args).unwrap(), board, "{}", fen);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_move_counters() {
+    let parse = |position: &str| -> Board {
+      let args: Vec<&str> = position.split(" ").collect();
+      Position::parse_game(&args).unwrap().board
+    };
+
+    let board = parse("position startpos moves g1f3 g8f6 f3g1 f6g8 g1f3");
+    assert_eq!((board.half_moves, board.full_moves), (5, 3));
+
+    let board = parse("position startpos moves g1f3 g8f6 f3g1 f6g8 e2e4");
+    assert_eq!((board.half_moves, board.full_moves), (0, 3));
+
+    let board = parse("position startpos moves e2e4 d7d5 g1f3 d5e4");
+    assert_eq!((board.half_moves, board.full_moves), (0, 3));
+
+    let board = parse("position fen 4k3/8/8/8/8/8/8/R3K3 w - - 99 80 moves a1a2");
+    assert_eq!((board.half_moves, board.full_moves), (100, 80));
+    assert!(board.is_fifty_move_draw());
+    assert!(!parse("position startpos").is_fifty_move_draw());
+  }
+
+  #[test]
+  fn test_move_packing() {
+    assert_eq!(std::mem::size_of::<Move>(), 2);
+
+    let m = Move::new(12, 28, Move::DOUBLE_PUSH);
+    assert_eq!((m.start(), m.dest(), m.flag()), (12, 28, Move::DOUBLE_PUSH));
+    assert!(m.is_double_push() && !m.is_castle() && !m.is_en_passant());
+    assert_eq!(m.promotion(), None);
+
+    for piece in Move::PROMOTIONS {
+      let m = Move::new_promotion(52, 61, piece);
+      assert_eq!((m.start(), m.dest(), m.promotion()), (52, 61, Some(piece)));
+    }
+
+    let board = Board::default();
+    for (uci, flag) in [
+      ("e2e4", Move::DOUBLE_PUSH),
+      ("e2e3", Move::QUIET),
+      ("g1f3", Move::QUIET),
+      ("e1g1", Move::CASTLE),
+      ("a7a8q", Move::PROMOTION + 3),
+      ("h2h1n", Move::PROMOTION),
+    ] {
+      let m = Move::from_uci(uci, &board).unwrap();
+      assert_eq!(m.flag(), flag);
+      assert_eq!(m.to_string(), uci);
+    }
+    assert!(Move::from_uci("e2", &board).is_err());
+    assert!(Move::from_uci("e7e8k", &board).is_err());
+
+    let position: Vec<&str> = "position startpos moves e2e4 d7d5 e4e5 f7f5"
+      .split(" ")
+      .collect();
+    let board = Position::parse_game(&position).unwrap().board;
+    let m = Move::from_uci("e5f6", &board).unwrap();
+    assert!(m.is_en_passant());
+    assert_eq!(board.captured_piece(m), Some(Pieces::PAWN));
+    assert_eq!(
+      board.captured_piece(Move::from_uci("e5e6", &board).unwrap()),
+      None
+    );
+  }
+
+  #[test]
+  fn test_move_list() {
+    let mut list = MoveList::new();
+    assert!(list.is_empty());
+
+    for dest in 16..24 {
+      list.push(Move::new(dest - 8, dest, Move::QUIET));
+    }
+    assert_eq!(list.len(), 8);
+    assert_eq!(list[3], Move::new(11, 19, Move::QUIET));
+
+    list.retain(|m| m.dest() % 2 == 0);
+    assert_eq!(
+      list.iter().map(|m| m.dest()).collect::<Vec<u8>>(),
+      vec![16, 18, 20, 22]
+    );
+
+    // Ties keep their order
+    list.score_moves(|m| {
+      if m.dest() == 16 {
+        0
+      } else {
+        m.dest() as i32 % 4
+      }
+    });
+    list.sort();
+    assert_eq!(
+      list.into_iter().map(|m| m.dest()).collect::<Vec<u8>>(),
+      vec![18, 22, 16, 20]
+    );
+
+    list.score_moves(|m| m.dest() as i32);
+    assert_eq!(list.pick(0).dest(), 22);
+    assert_eq!(list.pick(1).dest(), 20);
+    assert_eq!(list.score(1), 20);
+
+    let board = Board::default();
+    let moves = MoveGen::gen_moves(&board, Sides::WHITE, true);
+    assert_eq!(MoveList::from(&moves[..10]).len(), 10);
+  }
+
+  #[test]
+  fn test_mailbox() {
+    let board = Board::default();
+    assert_eq!(board.piece_at(4), Some((Sides::WHITE, Pieces::KING)));
+    assert_eq!(board.piece_at(57), Some((Sides::BLACK, Pieces::KNIGHT)));
+    assert_eq!(board.piece_at(28), None);
+
+    // Castling, en passant and a capture-promotion all move more than one piece
+    let position: Vec<&str> =
+      "position fen r3k2r/1P6/8/8/3pP3/8/8/R3K2R b KQkq e3 0 1 moves d4e3 e1c1 e8g8 b7a8n"
+        .split(" ")
+        .collect();
+    let board = Position::parse_game(&position).unwrap().board;
+    assert_eq!(board.mailbox, board.get_mailbox());
+    assert_eq!(board.piece_at(56), Some((Sides::WHITE, Pieces::KNIGHT)));
+    assert_eq!(board.piece_at(3), Some((Sides::WHITE, Pieces::ROOK)));
+    assert_eq!(board.piece_at(61), Some((Sides::BLACK, Pieces::ROOK)));
+    assert_eq!(board.piece_at(20), Some((Sides::BLACK, Pieces::PAWN)));
+    assert_eq!(board.piece_at(28), None);
+
+    let mut board = board;
+    let before = board;
+    let m = Move::from_uci("c1c8", &board).unwrap();
+    let undo = board.make_move(m);
+    assert_eq!(board.piece_at(58), Some((Sides::WHITE, Pieces::KING)));
+    board.unmake_move(m, undo);
+    assert_eq!(board, before);
+
+    let position: Vec<&str> = "position startpos moves e3e4".split(" ").collect();
+    assert!(Position::parse_game(&position).is_err());
+  }
+
+  #[test]
+  fn test_magic_attacks() {
+    let mut rng = StdRng::seed_from_u64(0x1D1D);
+
+    for _ in 0..200 {
+      // Sparse and dense boards both come up
+      let occupied = rng.gen::<u64>() & rng.gen::<u64>() | rng.gen::<u64>() & rng.gen::<u64>();
+
+      for square in 0..64 {
+        let rook = SlidingAttacks::ray_attacks(square, occupied, &ROOK_DIRECTIONS);
+        let bishop = SlidingAttacks::ray_attacks(square, occupied, &BISHOP_DIRECTIONS);
+
+        assert_eq!(SlidingAttacks::rook(square, occupied), rook);
+        assert_eq!(SlidingAttacks::bishop(square, occupied), bishop);
+        assert_eq!(SlidingAttacks::queen(square, occupied), rook | bishop);
+      }
+    }
+  }
+
+  #[test]
+  fn test_leaper_attacks() {
+    let squares = |names: &[&str]| {
+      names
+        .iter()
+        .fold(0, |bb, name| bb | 1u64 << alph_to_pos(name).unwrap())
+    };
+
+    assert_eq!(KNIGHT_ATTACKS[0], squares(&["b3", "c2"]));
+    assert_eq!(KNIGHT_ATTACKS[63], squares(&["g6", "f7"]));
+    assert_eq!(KNIGHT_ATTACKS[27].count_ones(), 8);
+    assert_eq!(KING_ATTACKS[63], squares(&["g8", "g7", "h7"]));
+    assert_eq!(KING_ATTACKS[7], squares(&["g1", "g2", "h2"]));
+    assert_eq!(PAWN_ATTACKS[0][8], squares(&["b3"]));
+    assert_eq!(PAWN_ATTACKS[1][55], squares(&["g6"]));
+
+    // The king used to never reach h8
+    let board = Fen::from_fen(&["6K1/8/8/8/8/8/8/k7", "w", "-", "-", "0", "1"]).unwrap();
+    let moves = MoveGen::gen_moves(&board, Sides::WHITE, true);
+    assert_eq!(moves.len(), 5);
+    assert!(moves.contains(&Move::new(62, 63, Move::QUIET)));
+  }
+
+  #[test]
+  fn test_validate() {
+    let from_fen = |fen: &str| {
+      let fen: Vec<&str> = fen.split(" ").collect();
+      Fen::from_fen(&fen)
+    };
+
+    assert!(from_fen("4k3/8/8/8/8/8/8/3KK3 w - - 0 1").is_err());
+    assert!(from_fen("4k3/8/8/8/8/8/8/4K3 w K - 0 1").is_err());
+    assert!(from_fen("4k3/4R3/8/8/8/8/8/4K3 w - - 0 1").is_err());
+    assert!(from_fen("4k3/8/8/8/8/8/8/4K3 w - e9 0 1").is_err());
+    assert!(from_fen("4k4/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+    assert!(from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").is_ok());
+    assert!(from_fen("4k3/8/8/8/8/8/8/4K3 w - z3 0 1").is_err());
+    assert!(from_fen("4k3/8/8/8/8/8/8/4K3 w - e? 0 1").is_err());
+    // A well formed but made up en passant square is dropped rather than rejected
+    assert_eq!(
+      from_fen("4k3/8/8/8/8/8/8/4K3 w - e6 0 1")
+        .unwrap()
+        .en_passant_square,
+      None
+    );
+    let board = Board {
+      en_passant_square: Some(44),
+      ..Default::default()
+    };
+    assert_eq!(board.validate(), Err(vec![BoardError::EnPassant(44)]));
+    // Rejected for the missing kings before en passant gets looked at
+    assert!(from_fen("8/8/8/4pP2/8/8/8/8 w - e6 0 1").is_err());
+    let pushed = from_fen("4k3/8/8/4pP2/8/8/8/4K3 w - e6 0 1").unwrap();
+    assert_eq!(pushed.en_passant_square, Some(44));
+
+    let mut board = Board::default();
+    board.bb_pieces[0][Pieces::PAWN as usize].0 |= 1 << 56;
+    board.bb_pieces[1][Pieces::KING as usize] = BitBoard(0);
+    board.white_can_ooo = true;
+    board.bb_pieces[0][Pieces::ROOK as usize] = BitBoard::from_pos(7);
+    board.bb_sides = board.get_sides();
+    board.mailbox = board.get_mailbox();
+
+    let errors = board.validate().unwrap_err();
+    assert!(errors.contains(&BoardError::OverlappingPieces(56)));
+    assert!(errors.contains(&BoardError::PawnOnBackRank(Sides::WHITE, 56)));
+    assert!(errors.contains(&BoardError::KingCount(Sides::BLACK, 0)));
+    assert!(errors.contains(&BoardError::CastlingRights(Sides::WHITE, false)));
+    assert!(!errors.contains(&BoardError::CastlingRights(Sides::WHITE, true)));
+    assert_eq!(Board::default().validate(), Ok(()));
+
+    let position: Vec<&str> = "position fen 4k3/4R3/8/8/8/8/8/4K3 w - - 0 1"
+      .split(" ")
+      .collect();
+    assert!(Position::parse_game(&position).is_err());
+    let position: Vec<&str> = "position fen 4k3/8/8".split(" ").collect();
+    assert!(Position::parse_game(&position).is_err());
+  }
+
+  #[test]
+  fn test_outcome() {
+    let outcome = |position: &str| -> Option<Outcome> {
+      let args: Vec<&str> = position.split(" ").collect();
+      Position::parse_game(&args).unwrap().outcome()
+    };
+
+    assert_eq!(outcome("position startpos"), None);
+    assert_eq!(
+      outcome("position startpos moves f2f3 e7e5 g2g4 d8h4"),
+      Some(Outcome::Checkmate(Sides::BLACK))
+    );
+    assert_eq!(
+      outcome("position fen 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"),
+      Some(Outcome::Stalemate)
+    );
+
+    let shuffle = "g1f3 g8f6 f3g1 f6g8";
+    let threefold = format!("position startpos moves {} {}", shuffle, shuffle);
+    assert_eq!(outcome(&threefold), Some(Outcome::ThreefoldRepetition));
+    let fivefold = format!("{} {} {}", threefold, shuffle, shuffle);
+    assert_eq!(outcome(&fivefold), Some(Outcome::FivefoldRepetition));
+    // A pawn move in between means the earlier positions can't come back
+    let reset = format!("position startpos moves {} e2e4 e7e5 {}", shuffle, shuffle);
+    assert_eq!(outcome(&reset), None);
+    // The en passant square after e7e5 can't be captured on, so it doesn't tell the positions apart
+    let after_push = format!(
+      "position startpos moves e2e4 e7e5 {} {} {} {}",
+      shuffle, shuffle, shuffle, shuffle
+    );
+    assert_eq!(outcome(&after_push), Some(Outcome::FivefoldRepetition));
+
+    let args: Vec<&str> = fivefold.split(" ").collect();
+    let mut game = Position::parse_game(&args).unwrap();
+    assert_eq!(game.repetitions(), 5);
+    game.unmake_move();
+    assert_eq!(game.repetitions(), 4);
+    assert_eq!(game.moves().len(), 15);
+
+    assert_eq!(
+      outcome("position fen 4k3/8/8/8/8/8/8/R3K3 w - - 100 80"),
+      Some(Outcome::FiftyMoves)
+    );
+    assert_eq!(
+      outcome("position fen 4k3/8/8/8/8/8/8/R3K3 w - - 150 80"),
+      Some(Outcome::SeventyFiveMoves)
+    );
+
+    assert_eq!(
+      outcome("position fen 4k3/8/8/8/8/8/8/2B1K3 w - - 0 1"),
+      Some(Outcome::InsufficientMaterial)
+    );
+    // Bishops on c1 and f8 are both on dark squares
+    assert_eq!(
+      outcome("position fen 4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1"),
+      Some(Outcome::InsufficientMaterial)
+    );
+    assert_eq!(
+      outcome("position fen 4k1b1/8/8/8/8/8/8/2B1K3 w - - 0 1"),
+      None
+    );
+    assert_eq!(
+      outcome("position fen 4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1"),
+      None
+    );
   }
 
   #[test]
@@ -147,9 +1023,313 @@ mod tests {
     );
   }
 
+  #[test]
+  fn test_time_allocation() {
+    let movetime = Time::parse_time(&["go", "movetime", "1500"]);
+    assert_eq!(movetime.movetime, Some(1500));
+    assert_eq!(
+      movetime.allocation(Sides::BLACK),
+      Some(Duration::from_millis(1500))
+    );
+
+    let clock = Time::parse_time(&["go", "wtime", "60000", "btime", "3000", "winc", "1000"]);
+    assert_eq!(
+      clock.allocation(Sides::WHITE),
+      Some(Duration::from_millis(2750))
+    );
+    assert_eq!(
+      clock.allocation(Sides::BLACK),
+      Some(Duration::from_millis(100))
+    );
+
+    assert_eq!(
+      Time::parse_time(&["go", "depth", "3"]).allocation(Sides::WHITE),
+      None
+    );
+    let flagged = Time::parse_time(&["go", "wtime", "0", "btime", "-20", "binc", "10"]);
+    assert_eq!(
+      flagged.allocation(Sides::WHITE),
+      Some(Duration::from_millis(1))
+    );
+    assert_eq!(
+      flagged.allocation(Sides::BLACK),
+      Some(Duration::from_millis(1))
+    );
+    assert_eq!(Time::parse_time(&["go"]).time, None);
+    assert_eq!(
+      Time::parse_time(&["go", "infinite"]).allocation(Sides::WHITE),
+      None
+    );
+    assert_eq!(
+      Time::parse_time(&["go", "ponder", "wtime", "1000", "btime", "1000"])
+        .allocation(Sides::WHITE),
+      None
+    );
+  }
+
+  #[test]
+  fn test_iterative_deepening() {
+    let mut pool = ThreadPool::new(4);
+
+    // Back rank mate in one for either side
+    let white = Fen::from_fen(&["6k1/5ppp/8/8/8/8/5PPP/R5K1", "w", "-", "-", "0", "1"]).unwrap();
+    let black = Fen::from_fen(&["r5k1/5ppp/8/8/8/8/5PPP/6K1", "b", "-", "-", "0", "1"]).unwrap();
+    let depth = Time::parse_time(&["go", "depth", "3"]);
+    assert_eq!(
+      pool.iterative_deepening(white, &depth),
+      Some(Move::new(0, 56, Move::QUIET))
+    );
+    assert_eq!(
+      pool.iterative_deepening(black, &depth),
+      Some(Move::new(56, 0, Move::QUIET))
+    );
+
+    // Depth 1 always gets to finish however small the node limit, even when it takes more than one
+    // batch of nodes, and plays the move it found rather than just any legal one
+    let busy = Fen::from_fen(&[
+      "2kr3r/pp1q1ppp/2n1bn2/2bpp3/2BPP3/2N1BN2/PPPQ1PPP/2KR3R",
+      "w",
+      "-",
+      "-",
+      "0",
+      "1",
+    ])
+    .unwrap();
+    let moves = MoveGen::gen_moves(&busy, busy.turn, true);
+    let mut engine = Engine::new();
+    engine.search_root(&mut busy.clone(), moves, 1);
+    assert!(engine.nodes > 1024);
+
+    pool.clear_hash();
+    let searched = pool.iterative_deepening(busy, &Time::parse_time(&["go", "depth", "1"]));
+    pool.clear_hash();
+    let m = pool.iterative_deepening(busy, &Time::parse_time(&["go", "nodes", "1"]));
+    assert!(m.is_some());
+    assert_eq!(m, searched);
+    assert_ne!(m, moves.first().copied());
+
+    // Close to a node limit every node is counted straight away, so it isn't overshot
+    let control = Arc::new(SearchControl::new(None, Some(3000)));
+    control.arm();
+    let mut engine = Engine::with_control(control.clone(), Arc::new(TranspositionTable::new(1)));
+    let moves = MoveGen::gen_moves(&busy, busy.turn, true);
+    engine.search_root(&mut busy.clone(), moves, 6);
+    engine.finish();
+    assert!(control.stopped());
+    assert_eq!(control.nodes(), 3000);
+
+    // Checkmated already, nothing to play
+    let mated = Fen::from_fen(&["R5k1/5ppp/8/8/8/8/5PPP/6K1", "b", "-", "-", "0", "1"]).unwrap();
+    assert_eq!(pool.iterative_deepening(mated, &depth), None);
+  }
+
+  #[test]
+  fn test_pvs() {
+    let search = |fen: &str, depth| {
+      let board = Fen::from_fen(&fen.split(" ").collect::<Vec<&str>>()).unwrap();
+      let moves = MoveGen::gen_moves(&board, board.turn, true);
+      Engine::new().search_root(&mut board.clone(), moves, depth)
+    };
+
+    // Mate in 2 with a knight sacrifice, scored from the side to move either way round
+    let (score, line) = search(
+      "r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1",
+      3,
+    );
+    assert_eq!(score, MATE_SCORE - 3.0);
+    assert_eq!(line.get(0), Some(Move::new(35, 45, Move::QUIET)));
+
+    // The start position is the same for either side to move
+    let white = search(
+      "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+      3,
+    )
+    .0;
+    let black = search(
+      "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1",
+      3,
+    )
+    .0;
+    assert_eq!(white, black);
+
+    // Fail soft: outside the window the score is a bound on the real one, and can be tighter than
+    // the edge of the window
+    let mut board = Board::default();
+    let exact = Engine::new().pvs(&mut board, -INFINITY, INFINITY, 2, 0);
+    let low = Engine::new().pvs(&mut board, exact + 1.0, exact + 2.0, 2, 0);
+    let high = Engine::new().pvs(&mut board, exact - 2.0, exact - 1.0, 2, 0);
+    assert!(exact <= low && low < exact + 1.0);
+    assert!(exact - 1.0 < high && high <= exact);
+  }
+
+  #[test]
+  fn test_null_move() {
+    let fen = "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3";
+    let mut board = Fen::from_fen(&fen.split(" ").collect::<Vec<&str>>()).unwrap();
+    let before = board;
+
+    let en_passant_square = board.make_null_move();
+    assert_eq!(en_passant_square, Some(43));
+    assert_eq!((board.turn, board.en_passant_square), (Sides::BLACK, None));
+    assert_eq!(board.hash, board.compute_hash());
+    assert_ne!(board.hash, before.hash);
+
+    board.unmake_null_move(en_passant_square);
+    assert_eq!(board, before);
+    assert_eq!(Move::NULL.to_string(), "0000");
+
+    assert!(board.has_non_pawn_material(Sides::WHITE));
+    let pawns = Fen::from_fen(&["8/5k2/3p4/8/3P4/8/4K3/6N1", "w", "-", "-", "0", "1"]).unwrap();
+    assert!(
+      pawns.has_non_pawn_material(Sides::WHITE) && !pawns.has_non_pawn_material(Sides::BLACK)
+    );
+
+    // Deep enough for null moves, passing never turns into a mate that isn't there
+    let board = Fen::from_fen(
+      &"r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1"
+        .split(" ")
+        .collect::<Vec<&str>>(),
+    )
+    .unwrap();
+    let moves = MoveGen::gen_moves(&board, board.turn, true);
+    let (score, line) = Engine::new().search_root(&mut board.clone(), moves, 4);
+    assert_eq!(score, MATE_SCORE - 3.0);
+    assert!(!line.moves().contains(&Move::NULL));
+  }
+
+  #[test]
+  fn test_late_moves() {
+    let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+    let board = Fen::from_fen(&fen.split(" ").collect::<Vec<&str>>()).unwrap();
+    let moves = MoveGen::gen_moves(&board, board.turn, true);
+
+    let mut engine = Engine::new();
+    engine.search_root(&mut board.clone(), moves, 4);
+    let stats = engine.stats;
+    assert!(stats.reductions > 0 && stats.late_move_prunes > 0);
+    assert!(stats.re_searches <= stats.reductions);
+
+    let mut total = stats;
+    total += stats;
+    assert_eq!(total.reductions, 2 * stats.reductions);
+    assert!(total.to_string().starts_with("info string null moves "));
+
+    // Reduced and pruned quiets don't hide a mate that starts with a quiet move
+    let mate = Fen::from_fen(&["6k1/5ppp/8/8/8/8/5PPP/R5K1", "w", "-", "-", "0", "1"]).unwrap();
+    let moves = MoveGen::gen_moves(&mate, mate.turn, true);
+    let (score, line) = Engine::new().search_root(&mut mate.clone(), moves, 4);
+    assert_eq!(score, MATE_SCORE - 1.0);
+    assert_eq!(line.get(0), Some(Move::new(0, 56, Move::QUIET)));
+  }
+
+  #[test]
+  fn test_transposition_table() {
+    let tt = TranspositionTable::new(1);
+    let entry = TTEntry {
+      depth: 5,
+      bound: Bound::Lower,
+      score: 1.25,
+      best_move: Some(Move::new(12, 28, Move::DOUBLE_PUSH)),
+      eval: Some(-0.3),
+    };
+
+    tt.store(0xdead_beef, 3, entry);
+    assert_eq!(tt.probe(0xdead_beef, 3), Some(entry));
+    assert_eq!(tt.probe(0xdead_beef ^ 1, 3), None);
+
+    // A mate found 4 plies from the root is a mate in 1 from the position, wherever it comes up
+    let mate = TTEntry {
+      score: MATE_SCORE - 4.0,
+      ..entry
+    };
+    tt.store(42, 3, mate);
+    assert_eq!(tt.probe(42, 5).unwrap().score, MATE_SCORE - 6.0);
+    let mated = TTEntry {
+      score: -(MATE_SCORE - 4.0),
+      ..entry
+    };
+    tt.store(42, 3, mated);
+    assert_eq!(tt.probe(42, 1).unwrap().score, -(MATE_SCORE - 2.0));
+
+    // Shallow bounds don't push out deeper results for the same position, but keep what they lack
+    let shallow = TTEntry {
+      depth: 1,
+      bound: Bound::Upper,
+      best_move: None,
+      eval: None,
+      ..entry
+    };
+    tt.store(0xdead_beef, 3, shallow);
+    assert_eq!(tt.probe(0xdead_beef, 3), Some(entry));
+    tt.store(
+      0xdead_beef,
+      3,
+      TTEntry {
+        depth: 4,
+        ..shallow
+      },
+    );
+    let kept = tt.probe(0xdead_beef, 3).unwrap();
+    assert_eq!((kept.depth, kept.bound), (4, Bound::Upper));
+    assert_eq!((kept.best_move, kept.eval), (entry.best_move, entry.eval));
+
+    // A full bucket gives up a fairly deep entry from an old search before one from this search
+    let tt = TranspositionTable::new(0);
+    for hash in 1..=4 {
+      tt.store(hash, 0, TTEntry { depth: 4, ..entry });
+    }
+    tt.new_search();
+    tt.store(5, 0, TTEntry { depth: 1, ..entry });
+    tt.store(6, 0, TTEntry { depth: 1, ..entry });
+    assert!(tt.probe(5, 0).is_some() && tt.probe(6, 0).is_some());
+    assert_eq!((1..=4).filter(|h| tt.probe(*h, 0).is_some()).count(), 2);
+    assert_eq!(tt.hashfull(), 500);
+
+    tt.clear();
+    assert_eq!(tt.hashfull(), 0);
+    assert!((1..=6).all(|h| tt.probe(h, 0).is_none()));
+  }
+
+  #[test]
+  fn test_search_info() {
+    let mut pv = Line::new();
+    pv.add_move(Move::new(12, 28, Move::DOUBLE_PUSH));
+    pv.add_move(Move::new(52, 36, Move::DOUBLE_PUSH));
+
+    let info = |score| {
+      SearchInfo {
+        depth: 2,
+        seldepth: 3,
+        score,
+        nodes: 5000,
+        hashfull: 12,
+        time: Duration::from_millis(500),
+        pv: &pv,
+      }
+      .to_string()
+    };
+
+    assert_eq!(
+      info(0.25),
+      "info depth 2 seldepth 3 score cp 25 nodes 5000 nps 10000 hashfull 12 time 500 pv e2e4 e7e5"
+    );
+    assert!(info(MATE_SCORE - 3.0).contains("score mate 2 "));
+    assert!(info(-(MATE_SCORE - 2.0)).contains("score mate -1 "));
+    assert!(is_mate(MATE_SCORE - 10.0) && !is_mate(150.0));
+  }
+
   #[test]
   fn test_alph_to_pos() {
-    assert_eq!(alph_to_pos("e5"), Ok(36))
+    assert_eq!(alph_to_pos("e5"), Ok(36));
+    for bad in ["a9", "a0", "i1", "e", "e10", ""] {
+      assert!(alph_to_pos(bad).is_err(), "{}", bad);
+    }
+
+    // Malformed squares are errors, not out of range panics
+    let board = Board::default();
+    for bad in ["a9a1", "a0a1", "e2e9", "e2e0q"] {
+      assert!(Move::from_uci(bad, &board).is_err(), "{}", bad);
+    }
   }
 
   #[test]
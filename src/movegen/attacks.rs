@@ -0,0 +1,99 @@
+use crate::structs::{BitBoard, Board, Pieces, Sides};
+
+use super::movegen::MoveGen;
+
+// Attacks of the pieces that jump rather than slide, one bitboard per square. They're built at
+// compile time so generation and attack detection are a single lookup
+
+pub const KNIGHT_ATTACKS: [u64; 64] = leaper_table(&[
+  (1, 2),
+  (2, 1),
+  (2, -1),
+  (1, -2),
+  (-1, -2),
+  (-2, -1),
+  (-2, 1),
+  (-1, 2),
+]);
+
+pub const KING_ATTACKS: [u64; 64] = leaper_table(&[
+  (1, 0),
+  (1, 1),
+  (0, 1),
+  (-1, 1),
+  (-1, 0),
+  (-1, -1),
+  (0, -1),
+  (1, -1),
+]);
+
+// Indexed by the side of the pawn, the squares it could capture on
+pub const PAWN_ATTACKS: [[u64; 64]; 2] = [
+  leaper_table(&[(-1, 1), (1, 1)]),
+  leaper_table(&[(-1, -1), (1, -1)]),
+];
+
+pub fn pawn_attacks(side: Sides, square: u8) -> u64 {
+  PAWN_ATTACKS[side as usize][square as usize]
+}
+
+const fn leaper_table(offsets: &[(i8, i8)]) -> [u64; 64] {
+  let mut table = [0; 64];
+
+  let mut square = 0;
+  while square < 64 {
+    let (file, rank) = ((square % 8) as i8, (square / 8) as i8);
+
+    let mut i = 0;
+    while i < offsets.len() {
+      let (f, r) = (file + offsets[i].0, rank + offsets[i].1);
+      if f >= 0 && f < 8 && r >= 0 && r < 8 {
+        table[square] |= 1 << (r * 8 + f);
+      }
+      i += 1;
+    }
+    square += 1;
+  }
+  table
+}
+
+// Attack queries on the current position, thin wrappers over the MoveGen versions
+impl Board {
+  pub fn occupied(&self) -> BitBoard {
+    self.bb_sides[0] | self.bb_sides[1]
+  }
+
+  // Pieces of both sides attacking the square, with the given pieces blocking sliders
+  pub fn attackers_to(&self, square: u8, occupied: BitBoard) -> BitBoard {
+    MoveGen::attackers_to(square, occupied, self)
+  }
+
+  pub fn is_square_attacked(&self, square: u8, by_side: Sides) -> bool {
+    MoveGen::is_square_attacked(square, self, by_side)
+  }
+
+  pub fn king_square(&self, side: Sides) -> u8 {
+    self.bb_pieces[side as usize][Pieces::KING as usize]
+      .0
+      .trailing_zeros() as u8
+  }
+
+  // Enemy pieces giving check to the side to move
+  pub fn checkers(&self) -> BitBoard {
+    self.attackers_to(self.king_square(self.turn), self.occupied())
+      & self.bb_sides[!self.turn as usize]
+  }
+
+  pub fn in_check(&self) -> bool {
+    self.checkers() != BitBoard(0)
+  }
+
+  // The side's pieces that can't leave the line between their king and an enemy slider
+  pub fn pinned(&self, side: Sides) -> BitBoard {
+    MoveGen::pins(self, side, self.king_square(side)).0
+  }
+
+  pub fn attacks(&self, side: Sides) -> BitBoard {
+    MoveGen::attacks(self, side)
+  }
+}
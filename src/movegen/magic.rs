@@ -0,0 +1,170 @@
+use std::sync::OnceLock;
+
+// Sliding piece attacks through magic bitboards. The relevant blockers of a square are multiplied
+// by a magic number so the top bits index straight into a table of precomputed attack sets. The
+// magics are searched for the first time the tables are used, seeded per rank so they come out the
+// same every run and are found quickly
+
+#[derive(Clone, Copy, Default)]
+struct Magic {
+  mask: u64,
+  magic: u64,
+  shift: u32,
+  offset: usize,
+}
+
+impl Magic {
+  fn index(&self, occupied: u64) -> usize {
+    self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+  }
+}
+
+pub struct SlidingAttacks {
+  rook: [Magic; 64],
+  bishop: [Magic; 64],
+  table: Vec<u64>,
+}
+
+static SLIDING_ATTACKS: OnceLock<SlidingAttacks> = OnceLock::new();
+
+const SEEDS: [u64; 8] = [728, 10316, 55013, 32803, 12281, 15100, 16645, 255];
+
+pub const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+pub const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (-1, 1), (1, -1), (-1, -1)];
+
+impl SlidingAttacks {
+  pub fn get() -> &'static Self {
+    SLIDING_ATTACKS.get_or_init(Self::generate)
+  }
+
+  pub fn rook(square: u8, occupied: u64) -> u64 {
+    let attacks = Self::get();
+    attacks.table[attacks.rook[square as usize].index(occupied)]
+  }
+
+  pub fn bishop(square: u8, occupied: u64) -> u64 {
+    let attacks = Self::get();
+    attacks.table[attacks.bishop[square as usize].index(occupied)]
+  }
+
+  pub fn queen(square: u8, occupied: u64) -> u64 {
+    Self::rook(square, occupied) | Self::bishop(square, occupied)
+  }
+
+  // Walks each ray until it leaves the board or hits a blocker, the blocker is included
+  pub fn ray_attacks(square: u8, occupied: u64, directions: &[(i8, i8); 4]) -> u64 {
+    let mut attacks = 0;
+    let (file, rank) = ((square % 8) as i8, (square / 8) as i8);
+
+    for (df, dr) in directions {
+      let (mut f, mut r) = (file + df, rank + dr);
+      while (0..8).contains(&f) && (0..8).contains(&r) {
+        let bit = 1u64 << (r * 8 + f);
+        attacks |= bit;
+        if occupied & bit != 0 {
+          break;
+        }
+        f += df;
+        r += dr;
+      }
+    }
+    attacks
+  }
+
+  // Blockers on the last square of a ray never change the attacks, so they are left out
+  fn relevant_mask(square: u8, directions: &[(i8, i8); 4]) -> u64 {
+    let mut mask = 0;
+    let (file, rank) = ((square % 8) as i8, (square / 8) as i8);
+
+    for (df, dr) in directions {
+      let (mut f, mut r) = (file + df, rank + dr);
+      while (0..8).contains(&(f + df)) && (0..8).contains(&(r + dr)) {
+        mask |= 1u64 << (r * 8 + f);
+        f += df;
+        r += dr;
+      }
+    }
+    mask
+  }
+
+  fn generate() -> Self {
+    let mut attacks = Self {
+      rook: [Magic::default(); 64],
+      bishop: [Magic::default(); 64],
+      table: vec![],
+    };
+
+    for square in 0..64 {
+      attacks.rook[square as usize] =
+        Self::find_magic(square, &ROOK_DIRECTIONS, &mut attacks.table);
+      attacks.bishop[square as usize] =
+        Self::find_magic(square, &BISHOP_DIRECTIONS, &mut attacks.table);
+    }
+    attacks
+  }
+
+  fn find_magic(square: u8, directions: &[(i8, i8); 4], table: &mut Vec<u64>) -> Magic {
+    let mask = Self::relevant_mask(square, directions);
+    let bits = mask.count_ones();
+
+    // Every subset of the mask with the attacks it gives (carry-rippler enumeration)
+    let mut occupancies: Vec<(u64, u64)> = Vec::with_capacity(1 << bits);
+    let mut subset: u64 = 0;
+    loop {
+      occupancies.push((subset, Self::ray_attacks(square, subset, directions)));
+      subset = subset.wrapping_sub(mask) & mask;
+      if subset == 0 {
+        break;
+      }
+    }
+
+    let mut entries: Vec<u64> = vec![0; 1 << bits];
+    let mut used: Vec<u32> = vec![0; 1 << bits];
+    let mut attempt = 0;
+    let mut seed = SEEDS[square as usize / 8];
+
+    loop {
+      // Magics with few set bits work far more often
+      let magic =
+        Self::next_random(&mut seed) & Self::next_random(&mut seed) & Self::next_random(&mut seed);
+      if (mask.wrapping_mul(magic) >> 56).count_ones() < 6 {
+        continue;
+      }
+
+      attempt += 1;
+      let candidate = Magic {
+        mask,
+        magic,
+        shift: 64 - bits,
+        offset: 0,
+      };
+
+      let collision = occupancies.iter().any(|(occupied, attack)| {
+        let index = candidate.index(*occupied);
+        if used[index] != attempt {
+          used[index] = attempt;
+          entries[index] = *attack;
+          false
+        } else {
+          entries[index] != *attack
+        }
+      });
+
+      if !collision {
+        let offset = table.len();
+        table.extend_from_slice(&entries);
+        return Magic {
+          offset,
+          ..candidate
+        };
+      }
+    }
+  }
+
+  fn next_random(seed: &mut u64) -> u64 {
+    *seed ^= *seed >> 12;
+    *seed ^= *seed << 25;
+    *seed ^= *seed >> 27;
+    seed.wrapping_mul(2685821657736338717)
+  }
+}
@@ -1 +1,5 @@
+pub mod attacks;
+pub mod magic;
 pub mod movegen;
+pub mod movelist;
+pub mod perft;
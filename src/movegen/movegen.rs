@@ -1,591 +1,445 @@
-use std::{cmp::min, vec};
+use super::{
+  attacks::{pawn_attacks, KING_ATTACKS, KNIGHT_ATTACKS},
+  magic::SlidingAttacks,
+  movelist::MoveList,
+};
 use crate::structs::{BitBoard, Board, Move, Pieces, Sides};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenType {
+  All,
+  Captures,
+  Quiets,
+  Evasions,
+}
+
 pub struct MoveGen;
 impl MoveGen {
   const ROW: i8 = 8;
 
-  pub fn gen_moves(board: Board, side: Sides, legal_check: bool) -> Vec<Move> {
-    let mut moves: Vec<Move> = vec![];
+  pub fn gen_moves(board: &Board, side: Sides, legal_check: bool) -> MoveList {
+    if legal_check {
+      return Self::legal_moves(board, side, GenType::All);
+    }
+
+    let mut moves = MoveList::new();
+    let targets = !board.bb_sides[side as usize];
 
     for (piece, bb) in board.bb_pieces[side as usize].into_iter().enumerate() {
-      match Pieces::from_usize(piece) {
-        Some(Pieces::PAWN) => moves.append(&mut Self::pawn_moves(bb, board, side)),
-        Some(Pieces::BISHOP) => moves.append(&mut Self::bishop_moves(bb, board, side)),
-        Some(Pieces::KNIGHT) => moves.append(&mut Self::knight_moves(bb, board, side)),
-        Some(Pieces::ROOK) => moves.append(&mut Self::rook_moves(bb, board, side)),
-        Some(Pieces::QUEEN) => moves.append(&mut Self::queen_moves(bb, board, side)),
-        Some(Pieces::KING) => moves.append(&mut Self::king_moves(bb, board, side)),
-
-        None => continue,
-      }
+      Self::piece_type_moves(&mut moves, piece, bb, board, side, targets);
     }
+    moves
+  }
 
-    if legal_check {
-      let mut legal_moves: Vec<Move> = vec![];
-      for m in moves {
-        let mut clone = board.clone();
-        clone.apply_move(m);
-
-        let opside_moves = Self::gen_moves(clone, !side, false);
+  // Captures, en passant and every promotion, quiet or not
+  pub fn captures(board: &Board) -> MoveList {
+    Self::legal_moves(board, board.turn, GenType::Captures)
+  }
 
-        let mut is_legal = true;
+  // Everything that isn't a capture or a promotion, castling included
+  pub fn quiets(board: &Board) -> MoveList {
+    Self::legal_moves(board, board.turn, GenType::Quiets)
+  }
 
-        for op_move in opside_moves {
-          if op_move.capture == Some(Pieces::KING) {
-            is_legal = false;
-            continue;
-          }
-        }
+  // All the ways out of check, nothing when the side to move isn't in check
+  pub fn evasions(board: &Board) -> MoveList {
+    Self::legal_moves(board, board.turn, GenType::Evasions)
+  }
 
-        if is_legal {
-          legal_moves.push(m);
-        }
-      }
-      moves = legal_moves;
+  // Legal moves straight from the position: the king only steps onto safe squares, in check the
+  // other pieces have to take the checker or block it, and pinned pieces stay on their pin ray
+  pub fn legal_moves(board: &Board, side: Sides, gen: GenType) -> MoveList {
+    let mut moves = MoveList::new();
+    let king = board.bb_pieces[side as usize][Pieces::KING as usize];
+    let king_square = king.0.trailing_zeros() as u8;
+    let occupied = board.bb_sides[0] | board.bb_sides[1];
+    let own = board.bb_sides[side as usize];
+    let enemies = board.bb_sides[!side as usize];
+
+    let checkers = Self::attackers_to(king_square, occupied, board) & enemies;
+    if gen == GenType::Evasions && checkers == BitBoard(0) {
+      return moves;
     }
 
-    moves
-  }
-
-  pub fn check_capture(p: u8, board: Board, side: Sides) -> Option<Pieces> {
-    let mut capture: Option<Pieces> = None;
+    // Which destinations this kind of move can have, pawns are sorted out separately since
+    // promotions count as captures whichever square they land on
+    let kind = match gen {
+      GenType::All | GenType::Evasions => !own,
+      GenType::Captures => enemies,
+      GenType::Quiets => !occupied,
+    };
 
-    if BitBoard::from_pos(p) & board.bb_sides[!side as usize] != BitBoard(0) {
-      for (piece, pieces) in board.bb_pieces[!side as usize].into_iter().enumerate() {
-        if pieces & BitBoard::from_pos(p) != BitBoard(0) {
-          capture = Pieces::from_usize(piece);
-        }
+    // The king is taken off the board first so it can't step back along a slider's ray
+    for dest in BitBoard(KING_ATTACKS[king_square as usize]) & kind {
+      if Self::attackers_to(dest, occupied ^ king, board) & enemies == BitBoard(0) {
+        moves.push(Move::new(king_square, dest, Move::QUIET));
       }
     }
-    capture
-  }
 
-  pub fn pawn_moves(pawns: BitBoard, board: Board, side: Sides) -> Vec<Move> {
-    let mut moves: Vec<Move> = vec![];
-    let empty_squares: BitBoard = !(board.bb_sides[0] | board.bb_sides[1]);
+    // Only the king can get out of a double check
+    if checkers.0.count_ones() > 1 {
+      return moves;
+    }
 
-    let direction = if side == Sides::WHITE {
-      Self::ROW
+    let targets = if checkers == BitBoard(0) {
+      if gen != GenType::Captures {
+        let castle_rights = Self::castle_rights(board, side);
+        Self::castle_moves(&mut moves, king_square, castle_rights, board, side);
+      }
+      !own
     } else {
-      -Self::ROW
+      checkers | Self::between(king_square, checkers.0.trailing_zeros() as u8)
     };
 
-    for s in 0..64 {
-      let position_bb = BitBoard::from_pos(s);
-
-      if position_bb & pawns != BitBoard(0) {
-        let edge_dists: (u8, u8) = (
-          //RIGHT, LEFT Shouldn't need top and bottom
-          8 - (s % 8) - 1,
-          8 - (8 - (s % 8)),
-        );
-
-        let target_square = if (s as i8).overflowing_add(direction).1 {
-          64 /* This becomes invalid by the if statement below */
-        } else {
-          (s as i8).overflowing_add(direction).0
-        } as u8;
-
-        let double_square = if !(s as i8).overflowing_add(direction * 2).1
-          && position_bb & Board::default().bb_pieces[side as usize][Pieces::PAWN as usize]
-            != BitBoard(0)
-        {
-          (s as i8).overflowing_add(direction * 2).0
-        } else {
-          64
-        } as u8;
-
-        let capture_squares: (u8, u8) = (
-          if (s as i8).overflowing_add(direction - 1).1 {
-            64
-          } else {
-            (s as i8).overflowing_add(direction - 1).0
-          } as u8,
-          if (s as i8).overflowing_add(direction + 1).1 {
-            64
-          } else {
-            (s as i8).overflowing_add(direction + 1).0
-          } as u8,
-        );
-
-        if target_square <= 63 {
-          let target_bb = BitBoard::from_pos(target_square);
-
-          if target_bb & empty_squares != BitBoard(0) {
-            moves.push(Move {
-              start: s,
-              dest: target_square,
-              capture: None,
-            });
-
-            if double_square <= 63 {
-              // Single moves has to be valid for the double move to be valid
-              let target_bb = BitBoard::from_pos(double_square);
-
-              if target_bb & empty_squares != BitBoard(0) {
-                moves.push(Move {
-                  start: s,
-                  dest: double_square,
-                  capture: None,
-                });
-              }
-            }
-          }
-        }
+    let (pinned, pin_rays) = Self::pins(board, side, king_square);
 
-        if capture_squares.0 <= 63 {
-          if edge_dists.1 != 0 {
-            let capture = Self::check_capture(capture_squares.0, board, side);
-
-            if capture.is_some() {
-              //println!("{} -> {} cap 0 ed: {:?}", pos_to_alph(s).unwrap(), pos_to_alph(capture_squares.0).unwrap(), edge_dists);
-              moves.push(Move {
-                start: s,
-                dest: capture_squares.0,
-                capture,
-              })
-            }
-          }
-        }
+    for (piece, bb) in board.bb_pieces[side as usize].into_iter().enumerate() {
+      if piece == Pieces::KING as usize {
+        continue;
+      }
 
-        if capture_squares.1 <= 63 {
-          if edge_dists.0 != 0 {
-            let capture = Self::check_capture(capture_squares.1, board, side);
-            if capture.is_some() {
-              //println!("{} -> {} cap 1 ed: {:?}", pos_to_alph(s).unwrap(), pos_to_alph(capture_squares.1).unwrap(), edge_dists);
-              moves.push(Move {
-                start: s,
-                dest: capture_squares.1,
-                capture,
-              })
-            }
-          }
-        }
+      let piece_targets = if piece == Pieces::PAWN as usize {
+        targets
+      } else {
+        targets & kind
+      };
+
+      Self::piece_type_moves(&mut moves, piece, bb & !pinned, board, side, piece_targets);
+      for s in bb & pinned {
+        let ray = BitBoard(pin_rays[s as usize]);
+        let pieces = BitBoard::from_pos(s);
+        Self::piece_type_moves(&mut moves, piece, pieces, board, side, piece_targets & ray);
       }
     }
-    moves
-  }
-
-  // Possible for this to use rays in future
-  pub fn bishop_moves(bishops: BitBoard, board: Board, side: Sides) -> Vec<Move> {
-    let mut moves: Vec<Move> = vec![];
-
-    for s in 0..64 {
-      let position_bb = BitBoard::from_pos(s);
-
-      if position_bb & bishops != BitBoard(0) {
-        let edge_dists: (u8, u8, u8, u8) = (
-          //RIGHT, LEFT, TOP, BOTTOM
-          8 - (s % 8) - 1,
-          8 - (8 - (s % 8)),
-          8 - (s / 8) - 1,
-          (s / 8),
-        );
-
-        // NE
-        for i in 1..min(edge_dists.0, edge_dists.2) + 1 {
-          let dest = s + 9 * i;
-          if dest > 63 || BitBoard::from_pos(dest) & board.bb_sides[side as usize] != BitBoard(0) {
-            break;
-          }
-
-          let capture = Self::check_capture(dest, board, side);
-
-          moves.push(Move {
-            start: s,
-            dest,
-            capture,
-          });
-
-          if capture.is_some() {
-            break;
-          } // Cant keep moving direction if there is a piece to capture
-        }
-
-        // NW
-        for i in 1..min(edge_dists.1, edge_dists.2) + 1 {
-          let dest = s + 7 * i;
-
-          if dest > 63 || BitBoard::from_pos(dest) & board.bb_sides[side as usize] != BitBoard(0) {
-            break;
-          }
 
-          let capture = Self::check_capture(dest, board, side);
+    let pawns = board.bb_pieces[side as usize][Pieces::PAWN as usize];
+    let is_pawn = |m: &Move| BitBoard::from_pos(m.start()) & pawns != BitBoard(0);
+    match gen {
+      GenType::Captures => moves.retain(|m| !is_pawn(m) || Self::is_tactical(board, *m)),
+      GenType::Quiets => moves.retain(|m| !is_pawn(m) || !Self::is_tactical(board, *m)),
+      GenType::All | GenType::Evasions => {}
+    }
 
-          moves.push(Move {
-            start: s,
-            dest: s + 7 * i,
-            capture,
-          });
+    // En passant moves two pieces at once, so it's simplest to check it on the board it leaves
+    moves.retain(|m| !m.is_en_passant() || Self::is_legal_en_passant(board, *m, king_square));
+    moves
+  }
 
-          if capture.is_some() {
-            break;
-          }
-        }
+  pub fn is_tactical(board: &Board, m: Move) -> bool {
+    m.promotion().is_some() || m.is_en_passant() || board.piece_at(m.dest()).is_some()
+  }
 
-        // SW
-        for i in 1..min(edge_dists.1, edge_dists.3) + 1 {
-          if s.overflowing_sub(9 * i).1
-            || BitBoard::from_pos(s - 9 * i) & board.bb_sides[side as usize] != BitBoard(0)
-          {
-            break;
-          }
+  fn piece_type_moves(
+    moves: &mut MoveList, piece: usize, bb: BitBoard, board: &Board, side: Sides, targets: BitBoard,
+  ) {
+    match Pieces::from_usize(piece) {
+      Some(Pieces::PAWN) => Self::pawn_moves(moves, bb, board, side, targets),
+      Some(Pieces::BISHOP) => Self::bishop_moves(moves, bb, board, targets),
+      Some(Pieces::KNIGHT) => Self::knight_moves(moves, bb, board, targets),
+      Some(Pieces::ROOK) => Self::rook_moves(moves, bb, board, targets),
+      Some(Pieces::QUEEN) => Self::queen_moves(moves, bb, board, targets),
+      Some(Pieces::KING) => Self::king_moves(moves, bb, board, side, targets),
+      None => {}
+    }
+  }
 
-          let capture = Self::check_capture(s - 9 * i, board, side);
+  // Every piece of either side that attacks the square with the given pieces in the way
+  pub fn attackers_to(square: u8, occupied: BitBoard, board: &Board) -> BitBoard {
+    let pieces =
+      |piece: Pieces| board.bb_pieces[0][piece as usize].0 | board.bb_pieces[1][piece as usize].0;
+    let diagonal = pieces(Pieces::BISHOP) | pieces(Pieces::QUEEN);
+    let straight = pieces(Pieces::ROOK) | pieces(Pieces::QUEEN);
+
+    BitBoard(
+      pawn_attacks(Sides::BLACK, square) & board.bb_pieces[0][Pieces::PAWN as usize].0
+        | pawn_attacks(Sides::WHITE, square) & board.bb_pieces[1][Pieces::PAWN as usize].0
+        | KNIGHT_ATTACKS[square as usize] & pieces(Pieces::KNIGHT)
+        | KING_ATTACKS[square as usize] & pieces(Pieces::KING)
+        | SlidingAttacks::bishop(square, occupied.0) & diagonal
+        | SlidingAttacks::rook(square, occupied.0) & straight,
+    )
+  }
 
-          moves.push(Move {
-            start: s,
-            dest: s - 9 * i,
-            capture,
-          });
+  // Every square the side attacks, whether or not a piece of its own is standing there
+  pub fn attacks(board: &Board, side: Sides) -> BitBoard {
+    let pieces = board.bb_pieces[side as usize];
+    let occupied = (board.bb_sides[0] | board.bb_sides[1]).0;
 
-          if capture.is_some() {
-            break;
-          }
-        }
+    let pawns = pieces[Pieces::PAWN as usize].0;
+    let (a_file, h_file) = (0x0101010101010101u64, 0x8080808080808080u64);
+    let mut attacks = if side == Sides::WHITE {
+      (pawns & !a_file) << 7 | (pawns & !h_file) << 9
+    } else {
+      (pawns & !a_file) >> 9 | (pawns & !h_file) >> 7
+    };
 
-        // SE
-        for i in 1..min(edge_dists.0, edge_dists.3) + 1 {
-          if s.overflowing_sub(7 * i).1
-            || BitBoard::from_pos(s - 7 * i) & board.bb_sides[side as usize] != BitBoard(0)
-          {
-            break;
-          }
+    for s in pieces[Pieces::KNIGHT as usize] {
+      attacks |= KNIGHT_ATTACKS[s as usize];
+    }
+    for s in pieces[Pieces::KING as usize] {
+      attacks |= KING_ATTACKS[s as usize];
+    }
+    for s in pieces[Pieces::BISHOP as usize] | pieces[Pieces::QUEEN as usize] {
+      attacks |= SlidingAttacks::bishop(s, occupied);
+    }
+    for s in pieces[Pieces::ROOK as usize] | pieces[Pieces::QUEEN as usize] {
+      attacks |= SlidingAttacks::rook(s, occupied);
+    }
+    BitBoard(attacks)
+  }
 
-          let capture = Self::check_capture(s - 7 * i, board, side);
+  // The squares strictly between two squares on the same line, nothing if they don't share one
+  pub fn between(a: u8, b: u8) -> BitBoard {
+    let (a_bb, b_bb) = (1u64 << a, 1u64 << b);
 
-          moves.push(Move {
-            start: s,
-            dest: s - 7 * i,
-            capture,
-          });
+    BitBoard(if SlidingAttacks::rook(a, 0) & b_bb != 0 {
+      SlidingAttacks::rook(a, b_bb) & SlidingAttacks::rook(b, a_bb)
+    } else if SlidingAttacks::bishop(a, 0) & b_bb != 0 {
+      SlidingAttacks::bishop(a, b_bb) & SlidingAttacks::bishop(b, a_bb)
+    } else {
+      0
+    })
+  }
 
-          if capture.is_some() {
-            break;
-          }
-        }
+  // Pieces that are the only thing between their king and an enemy slider, along with the squares
+  // each one can still move to: the ray up to and including the pinning piece
+  pub fn pins(board: &Board, side: Sides, king: u8) -> (BitBoard, [u64; 64]) {
+    let mut pinned = BitBoard(0);
+    let mut rays = [0; 64];
+    let enemy = board.bb_pieces[!side as usize];
+    let enemies = board.bb_sides[!side as usize].0;
+    let occupied = board.bb_sides[0] | board.bb_sides[1];
+
+    let diagonal = enemy[Pieces::BISHOP as usize].0 | enemy[Pieces::QUEEN as usize].0;
+    let straight = enemy[Pieces::ROOK as usize].0 | enemy[Pieces::QUEEN as usize].0;
+    let snipers = SlidingAttacks::bishop(king, enemies) & diagonal
+      | SlidingAttacks::rook(king, enemies) & straight;
+
+    for sniper in BitBoard(snipers) {
+      let between = Self::between(king, sniper);
+      let blockers = between & occupied;
+
+      if blockers.0.count_ones() == 1 && blockers & board.bb_sides[side as usize] != BitBoard(0) {
+        pinned = pinned | blockers;
+        rays[blockers.0.trailing_zeros() as usize] = between.0 | 1 << sniper;
       }
     }
-    moves
+    (pinned, rays)
   }
 
-  pub fn knight_moves(knights: BitBoard, board: Board, side: Sides) -> Vec<Move> {
-    let mut moves: Vec<Move> = vec![];
-
-    const SHIFTS: [i8; 8] = [6, 10, 15, 17, -6, -10, -15, -17];
-
-    for s in 0..64 {
-      let position_bb = BitBoard::from_pos(s);
-
-      if position_bb & knights != BitBoard(0) {
-        let edge_dists: (u8, u8, u8, u8) = (
-          //RIGHT, LEFT, TOP, BOTTOM
-          8 - (s % 8) - 1,
-          8 - (8 - (s % 8)),
-          8 - (s / 8) - 1,
-          (s / 8),
-        );
+  // Both pawns leave their squares, which can uncover a slider along the rank as well as a pin
+  fn is_legal_en_passant(board: &Board, m: Move, king: u8) -> bool {
+    let side = board.turn;
+    let captured = if side == Sides::WHITE {
+      m.dest() - 8
+    } else {
+      m.dest() + 8
+    };
 
-        for shift in SHIFTS {
-          let dest = (s as i8).wrapping_add(shift);
+    let occupied = (board.bb_sides[0] | board.bb_sides[1])
+      ^ BitBoard::from_pos(m.start())
+      ^ BitBoard::from_pos(captured)
+      | BitBoard::from_pos(m.dest());
+    let enemies = board.bb_sides[!side as usize] ^ BitBoard::from_pos(captured);
 
-          if dest > 63 || dest as u8 == s || dest < 0 {
-            continue;
-          }
+    Self::attackers_to(king, occupied, board) & enemies == BitBoard(0)
+  }
 
-          // EDGE DISTS
-          // 0 = RIGHT
-          // 1 = LEFT
-          // 2 = TOP
-          // 3 = BOTTOM
-
-          // SHIFTS
-          // 6 = UP 2LEFT
-          // -6 = DOWN 2RIGHT
-          // 10 = UP 2RIGHT
-          // -10 = DOWN 2LEFT
-          // 15 = 2UP LEFT
-          // -15 = 2DOWN RIGHT
-          // 17 = 2UP RIGHT
-          // -17 = 2DOWN LEFT
-
-          let is_valid_move = match shift {
-            6 => edge_dists.1 >= 2 && edge_dists.2 >= 1,
-            10 => edge_dists.0 >= 2 && edge_dists.2 >= 1,
-            15 => edge_dists.1 >= 1 && edge_dists.2 >= 2,
-            17 => edge_dists.0 >= 1 && edge_dists.2 >= 2,
-
-            -6 => edge_dists.0 >= 2 && edge_dists.3 >= 1,
-            -10 => edge_dists.1 >= 2 && edge_dists.3 >= 1,
-            -15 => edge_dists.0 >= 1 && edge_dists.3 >= 2,
-            -17 => edge_dists.1 >= 1 && edge_dists.3 >= 2,
-            _ => false,
-          };
-
-          if is_valid_move {
-            let dest_bb = BitBoard::from_pos(dest.try_into().unwrap());
-
-            if dest_bb & board.bb_sides[side as usize] == BitBoard(0) {
-              let capture = Self::check_capture(dest.try_into().unwrap(), board, side);
-
-              moves.push(Move {
-                start: s,
-                dest: dest.try_into().unwrap(),
-                capture,
-              })
-            }
-          }
-        }
-      }
-    }
-    moves
+  // Whether the side to move has a legal en passant capture onto the square
+  pub fn can_capture_en_passant(board: &Board, square: u8) -> bool {
+    let side = board.turn;
+    let pawns =
+      pawn_attacks(!side, square) & board.bb_pieces[side as usize][Pieces::PAWN as usize].0;
+    let king = board.king_square(side);
+    BitBoard(pawns)
+      .any(|pawn| Self::is_legal_en_passant(board, Move::new(pawn, square, Move::EN_PASSANT), king))
   }
 
-  pub fn rook_moves(rooks: BitBoard, board: Board, side: Sides) -> Vec<Move> {
-    let mut moves: Vec<Move> = vec![];
-
-    for s in 0..64 {
-      let position_bb = BitBoard::from_pos(s);
-      if position_bb & rooks != BitBoard(0) {
-        let edge_dists: (u8, u8, u8, u8) = (
-          //RIGHT, LEFT, TOP, BOTTOM
-          8 - (s % 8) - 1,
-          8 - (8 - (s % 8)),
-          8 - (s / 8) - 1,
-          (s / 8),
-        );
-
-        for i in 1..(edge_dists.0 + 1) {
-          if s + i > 63 || BitBoard::from_pos(s + i) & board.bb_sides[side as usize] != BitBoard(0)
-          {
-            break;
-          }
+  // Only moves landing on the targets are generated, except en passant which is always included
+  pub fn pawn_moves(
+    moves: &mut MoveList, pawns: BitBoard, board: &Board, side: Sides, targets: BitBoard,
+  ) {
+    let empty_squares = !(board.bb_sides[0] | board.bb_sides[1]).0;
+    let enemies = board.bb_sides[!side as usize].0 & targets.0;
 
-          let capture = Self::check_capture(s + i, board, side);
+    let (direction, start_rank) = if side == Sides::WHITE {
+      (Self::ROW, 1)
+    } else {
+      (-Self::ROW, 6)
+    };
 
-          moves.push(Move {
-            start: s,
-            dest: s + i,
-            capture,
-          });
+    for s in pawns {
+      let target_square = (s as i8 + direction) as u8;
 
-          if capture.is_some() {
-            break;
-          }
+      // A pawn is never on the last rank, so one step forward always stays on the board
+      if BitBoard::from_pos(target_square).0 & empty_squares != 0 {
+        if BitBoard::from_pos(target_square).0 & targets.0 != 0 {
+          Self::push_pawn_move(moves, s, target_square, Move::QUIET, side);
         }
 
-        for i in 1..(edge_dists.1 + 1) {
-          if s.overflowing_sub(i).1
-            || BitBoard::from_pos(s - i) & board.bb_sides[side as usize] != BitBoard(0)
-          {
-            break;
-          }
-
-          let capture = Self::check_capture(s - i, board, side);
+        // Single moves has to be valid for the double move to be valid
+        let double_square = (target_square as i8 + direction) as u8;
+        if s / 8 == start_rank
+          && BitBoard::from_pos(double_square).0 & empty_squares & targets.0 != 0
+        {
+          moves.push(Move::new(s, double_square, Move::DOUBLE_PUSH));
+        }
+      }
 
-          moves.push(Move {
-            start: s,
-            dest: s - i,
-            capture,
-          });
+      let attacks = pawn_attacks(side, s);
 
-          if capture.is_some() {
-            break;
+      if side == board.turn {
+        if let Some(en_passant) = board.en_passant_square {
+          if BitBoard::from_pos(en_passant).0 & attacks != 0 {
+            moves.push(Move::new(s, en_passant, Move::EN_PASSANT));
           }
         }
+      }
 
-        for i in 1..(edge_dists.2 + 1) {
-          if s + i * 8 > 63
-            || BitBoard::from_pos(s + i * 8) & board.bb_sides[side as usize] != BitBoard(0)
-          {
-            break;
-          }
+      for dest in BitBoard(attacks & enemies) {
+        Self::push_pawn_move(moves, s, dest, Move::QUIET, side);
+      }
+    }
+  }
 
-          let capture = Self::check_capture(s + i * 8, board, side);
+  // Pawns reaching the last rank push one move per promotion piece instead of a plain move
+  fn push_pawn_move(moves: &mut MoveList, start: u8, dest: u8, flag: u16, side: Sides) {
+    let last_rank = if side == Sides::WHITE { 7 } else { 0 };
 
-          moves.push(Move {
-            start: s,
-            dest: s + i * 8,
-            capture,
-          });
+    if dest / 8 != last_rank {
+      moves.push(Move::new(start, dest, flag));
+      return;
+    }
 
-          if capture.is_some() {
-            break;
-          }
-        }
+    for promotion in [Pieces::QUEEN, Pieces::ROOK, Pieces::BISHOP, Pieces::KNIGHT] {
+      moves.push(Move::new_promotion(start, dest, promotion));
+    }
+  }
 
-        for i in 1..(edge_dists.3 + 1) {
-          if s.overflowing_sub(i * 8).1
-            || BitBoard::from_pos(s - i * 8) & board.bb_sides[side as usize] != BitBoard(0)
-          {
-            break;
-          }
+  pub fn bishop_moves(moves: &mut MoveList, bishops: BitBoard, board: &Board, targets: BitBoard) {
+    Self::piece_moves(moves, bishops, board, targets, SlidingAttacks::bishop)
+  }
 
-          let capture = Self::check_capture(s - i * 8, board, side);
+  pub fn knight_moves(moves: &mut MoveList, knights: BitBoard, board: &Board, targets: BitBoard) {
+    Self::piece_moves(moves, knights, board, targets, |s, _| {
+      KNIGHT_ATTACKS[s as usize]
+    })
+  }
 
-          moves.push(Move {
-            start: s,
-            dest: s - i * 8,
-            capture,
-          });
+  pub fn rook_moves(moves: &mut MoveList, rooks: BitBoard, board: &Board, targets: BitBoard) {
+    Self::piece_moves(moves, rooks, board, targets, SlidingAttacks::rook)
+  }
 
-          if capture.is_some() {
-            break;
-          }
-        }
+  pub fn queen_moves(moves: &mut MoveList, queens: BitBoard, board: &Board, targets: BitBoard) {
+    Self::piece_moves(moves, queens, board, targets, SlidingAttacks::queen)
+  }
+
+  // Every target square the pieces attack, the targets must not include the side's own pieces
+  fn piece_moves(
+    moves: &mut MoveList, pieces: BitBoard, board: &Board, targets: BitBoard,
+    attacks: fn(u8, u64) -> u64,
+  ) {
+    let occupied = (board.bb_sides[0] | board.bb_sides[1]).0;
+
+    for s in pieces {
+      for dest in BitBoard(attacks(s, occupied) & targets.0) {
+        moves.push(Move::new(s, dest, Move::QUIET));
       }
     }
-    moves
   }
 
-  pub fn queen_moves(bb: BitBoard, board: Board, side: Sides) -> Vec<Move> {
-    let mut moves: Vec<Move> = vec![];
-    moves.append(&mut Self::bishop_moves(bb, board, side));
-    moves.append(&mut Self::rook_moves(bb, board, side));
-    // println!("Queen Moves: {:#?} \n Len: {}", moves, moves.len());
-    moves
+  pub fn king_moves(
+    moves: &mut MoveList, kings: BitBoard, board: &Board, side: Sides, targets: BitBoard,
+  ) {
+    let castle_rights = Self::castle_rights(board, side);
+
+    for s in kings {
+      Self::piece_moves(moves, BitBoard::from_pos(s), board, targets, |s, _| {
+        KING_ATTACKS[s as usize]
+      });
+      Self::castle_moves(moves, s, castle_rights, board, side);
+    }
   }
 
-  pub fn king_moves(kings: BitBoard, board: Board, side: Sides) -> Vec<Move> {
-    let mut moves: Vec<Move> = vec![];
+  // King side then queen side
+  fn castle_rights(board: &Board, side: Sides) -> (bool, bool) {
+    if side == Sides::WHITE {
+      (board.white_can_oo, board.white_can_ooo)
+    } else {
+      (board.black_can_oo, board.black_can_ooo)
+    }
+  }
 
-    let castle_rights: (bool, bool) = (
-      if side == Sides::WHITE {
-        board.white_can_oo
-      } else {
-        board.black_can_oo
-      },
-      if side == Sides::WHITE {
-        board.white_can_ooo
-      } else {
-        board.black_can_ooo
-      },
-    );
-
-    for s in 0..64 {
-      let position_bb = BitBoard::from_pos(s);
-
-      if position_bb & kings != BitBoard(0) {
-        let edge_dists: (u8, u8, u8, u8) = (
-          //RIGHT, LEFT, TOP, BOTTOM
-          8 - (s % 8) - 1,
-          8 - (8 - (s % 8)),
-          8 - (s / 8) - 1,
-          (s / 8),
-        );
-
-        if edge_dists.0 != 0 {
-          if s + 1 < 63 && BitBoard::from_pos(s + 1) & board.bb_sides[side as usize] == BitBoard(0)
-          {
-            let capture = Self::check_capture(s + 1, board, side);
-
-            moves.push(Move {
-              start: s,
-              dest: s + 1,
-              capture,
-            });
-          }
+  // The king and rook have to be on their starting squares, everything between them has to be
+  // empty and the king can't castle out of, through or into check
+  fn castle_moves(
+    moves: &mut MoveList, king: u8, castle_rights: (bool, bool), board: &Board, side: Sides,
+  ) {
+    let home = if side == Sides::WHITE { 4 } else { 60 };
+    let rooks = board.bb_pieces[side as usize][Pieces::ROOK as usize];
+    let occupied = board.bb_sides[0] | board.bb_sides[1];
+
+    if king != home || Self::is_square_attacked(king, board, !side) {
+      return;
+    }
 
-          if edge_dists.2 != 0 {
-            if s + 9 < 63
-              && BitBoard::from_pos(s + 9) & board.bb_sides[side as usize] == BitBoard(0)
-            {
-              let capture = Self::check_capture(s + 9, board, side);
-
-              moves.push(Move {
-                start: s,
-                dest: s + 9,
-                capture,
-              });
-            }
-          }
+    // (allowed, rook square, squares that must be empty, squares the king crosses, king dest)
+    let castles: [(bool, u8, &[u8], [u8; 2], u8); 2] = [
+      (
+        castle_rights.0,
+        home + 3,
+        &[home + 1, home + 2],
+        [home + 1, home + 2],
+        home + 2,
+      ),
+      (
+        castle_rights.1,
+        home - 4,
+        &[home - 1, home - 2, home - 3],
+        [home - 1, home - 2],
+        home - 2,
+      ),
+    ];
+
+    for (allowed, rook, empty, crossed, dest) in castles {
+      if !allowed || BitBoard::from_pos(rook) & rooks == BitBoard(0) {
+        continue;
+      }
 
-          if edge_dists.3 != 0 {
-            if !s.overflowing_sub(7).1
-              && BitBoard::from_pos(s - 7) & board.bb_sides[side as usize] == BitBoard(0)
-            {
-              let capture = Self::check_capture(s - 7, board, side);
-
-              moves.push(Move {
-                start: s,
-                dest: s - 7,
-                capture,
-              });
-            }
-          }
-        }
+      if empty
+        .iter()
+        .any(|s| BitBoard::from_pos(*s) & occupied != BitBoard(0))
+      {
+        continue;
+      }
 
-        if edge_dists.1 != 0 {
-          if !s.overflowing_sub(1).1
-            && BitBoard::from_pos(s - 1) & board.bb_sides[side as usize] == BitBoard(0)
-          {
-            let capture = Self::check_capture(s - 1, board, side);
-
-            moves.push(Move {
-              start: s,
-              dest: s - 1,
-              capture,
-            });
-          }
+      if crossed
+        .iter()
+        .any(|s| Self::is_square_attacked(*s, board, !side))
+      {
+        continue;
+      }
 
-          if edge_dists.2 != 0 {
-            if s + 7 < 63
-              && BitBoard::from_pos(s + 7) & board.bb_sides[side as usize] == BitBoard(0)
-            {
-              let capture = Self::check_capture(s + 7, board, side);
-
-              moves.push(Move {
-                start: s,
-                dest: s + 7,
-                capture,
-              });
-            }
-          }
+      moves.push(Move::new(king, dest, Move::CASTLE));
+    }
+  }
 
-          if edge_dists.3 != 0 {
-            if !s.overflowing_sub(9).1
-              && BitBoard::from_pos(s - 9) & board.bb_sides[side as usize] == BitBoard(0)
-            {
-              let capture = Self::check_capture(s - 9, board, side);
-
-              moves.push(Move {
-                start: s,
-                dest: s - 9,
-                capture,
-              });
-            }
-          }
-        }
+  pub fn is_square_attacked(square: u8, board: &Board, by_side: Sides) -> bool {
+    let pieces = board.bb_pieces[by_side as usize];
+    let occupied = board.bb_sides[0] | board.bb_sides[1];
 
-        if edge_dists.2 != 0 {
-          if s + 8 < 63 && BitBoard::from_pos(s + 8) & board.bb_sides[side as usize] == BitBoard(0)
-          {
-            let capture = Self::check_capture(s + 8, board, side);
+    // A pawn attacks the square if a pawn of ours on the square would attack it back
+    if pawn_attacks(!by_side, square) & pieces[Pieces::PAWN as usize].0 != 0
+      || KNIGHT_ATTACKS[square as usize] & pieces[Pieces::KNIGHT as usize].0 != 0
+      || KING_ATTACKS[square as usize] & pieces[Pieces::KING as usize].0 != 0
+    {
+      return true;
+    }
 
-            moves.push(Move {
-              start: s,
-              dest: s + 8,
-              capture,
-            });
-          }
-        }
+    let diagonal = pieces[Pieces::BISHOP as usize] | pieces[Pieces::QUEEN as usize];
+    let straight = pieces[Pieces::ROOK as usize] | pieces[Pieces::QUEEN as usize];
 
-        if edge_dists.3 != 0 {
-          if !s.overflowing_sub(8).1
-            && BitBoard::from_pos(s - 8) & board.bb_sides[side as usize] == BitBoard(0)
-          {
-            let capture = Self::check_capture(s - 8, board, side);
-
-            moves.push(Move {
-              start: s,
-              dest: s - 8,
-              capture,
-            });
-          }
-        }
-      }
-    }
-    moves
+    SlidingAttacks::bishop(square, occupied.0) & diagonal.0 != 0
+      || SlidingAttacks::rook(square, occupied.0) & straight.0 != 0
   }
 }
@@ -0,0 +1,177 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::structs::Move;
+
+// No legal position has more than 218 moves, so a fixed array on the stack always fits them and
+// generating never touches the heap
+pub const MAX_MOVES: usize = 256;
+
+#[derive(Clone, Copy)]
+pub struct MoveList {
+  moves: [Move; MAX_MOVES],
+  // Ordering scores, only meaningful after one of the scoring methods has run
+  scores: [i32; MAX_MOVES],
+  len: usize,
+}
+
+impl MoveList {
+  pub fn new() -> Self {
+    Self {
+      moves: [Move(0); MAX_MOVES],
+      scores: [0; MAX_MOVES],
+      len: 0,
+    }
+  }
+
+  pub fn push(&mut self, m: Move) {
+    debug_assert!(self.len < MAX_MOVES, "Move list is full");
+    self.moves[self.len] = m;
+    self.scores[self.len] = 0;
+    self.len += 1;
+  }
+
+  // Appends the other list's moves along with their scores
+  pub fn extend(&mut self, other: &Self) {
+    debug_assert!(self.len + other.len <= MAX_MOVES, "Move list is full");
+    self.moves[self.len..self.len + other.len].copy_from_slice(&other.moves[..other.len]);
+    self.scores[self.len..self.len + other.len].copy_from_slice(&other.scores[..other.len]);
+    self.len += other.len;
+  }
+
+  pub fn clear(&mut self) {
+    self.len = 0;
+  }
+
+  // Keeps the order of the moves that stay
+  pub fn retain(&mut self, mut keep: impl FnMut(&Move) -> bool) {
+    let mut kept = 0;
+    for i in 0..self.len {
+      if keep(&self.moves[i]) {
+        self.moves[kept] = self.moves[i];
+        self.scores[kept] = self.scores[i];
+        kept += 1;
+      }
+    }
+    self.len = kept;
+  }
+
+  pub fn score(&self, index: usize) -> i32 {
+    self.scores[index]
+  }
+
+  pub fn score_moves(&mut self, mut score: impl FnMut(Move) -> i32) {
+    for i in 0..self.len {
+      self.scores[i] = score(self.moves[i]);
+    }
+  }
+
+  // Highest score first, equal scores keep their generation order
+  pub fn sort(&mut self) {
+    for i in 1..self.len {
+      let (m, score) = (self.moves[i], self.scores[i]);
+      let mut j = i;
+      while j > 0 && self.scores[j - 1] < score {
+        self.moves[j] = self.moves[j - 1];
+        self.scores[j] = self.scores[j - 1];
+        j -= 1;
+      }
+      self.moves[j] = m;
+      self.scores[j] = score;
+    }
+  }
+
+  // Swaps the best scoring move from index onwards into index, cheaper than sorting everything
+  // when a cutoff is likely after the first few moves
+  pub fn pick(&mut self, index: usize) -> Move {
+    let best = (index..self.len)
+      .max_by_key(|i| (self.scores[*i], std::cmp::Reverse(*i)))
+      .unwrap_or(index);
+
+    self.moves.swap(index, best);
+    self.scores.swap(index, best);
+    self.moves[index]
+  }
+}
+
+impl Default for MoveList {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl From<&[Move]> for MoveList {
+  fn from(moves: &[Move]) -> Self {
+    let mut list = Self::new();
+    for m in moves {
+      list.push(*m);
+    }
+    list
+  }
+}
+
+impl Deref for MoveList {
+  type Target = [Move];
+
+  fn deref(&self) -> &Self::Target {
+    &self.moves[..self.len]
+  }
+}
+
+impl DerefMut for MoveList {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.moves[..self.len]
+  }
+}
+
+impl std::fmt::Debug for MoveList {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_list().entries(self.iter()).finish()
+  }
+}
+
+impl PartialEq for MoveList {
+  fn eq(&self, other: &Self) -> bool {
+    **self == **other
+  }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+  type Item = &'a Move;
+  type IntoIter = std::slice::Iter<'a, Move>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.iter()
+  }
+}
+
+pub struct IntoIter {
+  list: MoveList,
+  index: usize,
+}
+
+impl Iterator for IntoIter {
+  type Item = Move;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let m = self.list.get(self.index).copied();
+    self.index += 1;
+    m
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let remaining = self.list.len - self.index.min(self.list.len);
+    (remaining, Some(remaining))
+  }
+}
+
+impl IntoIterator for MoveList {
+  type Item = Move;
+  type IntoIter = IntoIter;
+
+  fn into_iter(self) -> Self::IntoIter {
+    IntoIter {
+      list: self,
+      index: 0,
+    }
+  }
+}
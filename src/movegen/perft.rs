@@ -0,0 +1,142 @@
+use std::{mem::size_of, thread};
+
+use crate::structs::{Board, Move};
+
+use super::movegen::MoveGen;
+
+// Counts the leaf nodes of the legal move tree, the standard way of checking move generation
+// against known results
+
+#[derive(Debug, Clone, Copy)]
+pub struct PerftOptions {
+  pub threads: usize,
+  // Size of each thread's table in megabytes, 0 turns hashing off
+  pub hash_mb: usize,
+}
+
+impl Default for PerftOptions {
+  fn default() -> Self {
+    Self {
+      threads: 1,
+      hash_mb: 0,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Default)]
+struct PerftEntry {
+  hash: u64,
+  depth: u8,
+  nodes: u64,
+}
+
+// Node counts of positions already seen, transpositions are very common deep in the tree
+pub struct PerftTable {
+  entries: Vec<PerftEntry>,
+}
+
+impl PerftTable {
+  pub fn new(mb: usize) -> Self {
+    let size = (mb * 1024 * 1024 / size_of::<PerftEntry>()).max(1);
+    Self {
+      entries: vec![PerftEntry::default(); size],
+    }
+  }
+
+  fn probe(&self, hash: u64, depth: u8) -> Option<u64> {
+    let entry = self.entries[hash as usize % self.entries.len()];
+    (entry.hash == hash && entry.depth == depth).then_some(entry.nodes)
+  }
+
+  fn store(&mut self, hash: u64, depth: u8, nodes: u64) {
+    let index = hash as usize % self.entries.len();
+    self.entries[index] = PerftEntry { hash, depth, nodes };
+  }
+}
+
+impl Board {
+  pub fn perft(&mut self, depth: u8) -> u64 {
+    Self::perft_node(self, depth, &mut None)
+  }
+
+  // Nodes under each legal move, which narrows a wrong count down to the move causing it
+  pub fn divide(&self, depth: u8) -> Vec<(Move, u64)> {
+    self.divide_with(depth, PerftOptions::default())
+  }
+
+  pub fn perft_with(&self, depth: u8, options: PerftOptions) -> u64 {
+    if depth == 0 {
+      return 1;
+    }
+    self
+      .divide_with(depth, options)
+      .iter()
+      .map(|(_, n)| n)
+      .sum()
+  }
+
+  // The root moves are shared out between the threads, each searching its own copy of the board
+  pub fn divide_with(&self, depth: u8, options: PerftOptions) -> Vec<(Move, u64)> {
+    let moves = MoveGen::gen_moves(self, self.turn, true);
+    if depth == 0 || moves.is_empty() {
+      return vec![];
+    }
+
+    let chunk_size = moves.len().div_ceil(options.threads.max(1));
+
+    thread::scope(|scope| {
+      let handles: Vec<_> = moves
+        .chunks(chunk_size)
+        .map(|chunk| {
+          let mut board = *self;
+          scope.spawn(move || {
+            let mut table = (options.hash_mb > 0).then(|| PerftTable::new(options.hash_mb));
+
+            chunk
+              .iter()
+              .map(|m| {
+                let undo = board.make_move(*m);
+                let nodes = Self::perft_node(&mut board, depth - 1, &mut table);
+                board.unmake_move(*m, undo);
+                (*m, nodes)
+              })
+              .collect::<Vec<(Move, u64)>>()
+          })
+        })
+        .collect();
+
+      handles
+        .into_iter()
+        .flat_map(|handle| handle.join().unwrap())
+        .collect()
+    })
+  }
+
+  fn perft_node(board: &mut Board, depth: u8, table: &mut Option<PerftTable>) -> u64 {
+    if depth == 0 {
+      return 1;
+    }
+
+    let moves = MoveGen::gen_moves(board, board.turn, true);
+    // The last ply doesn't need playing out, every legal move is one leaf
+    if depth == 1 {
+      return moves.len() as u64;
+    }
+
+    if let Some(nodes) = table.as_ref().and_then(|t| t.probe(board.hash, depth)) {
+      return nodes;
+    }
+
+    let mut nodes = 0;
+    for m in moves {
+      let undo = board.make_move(m);
+      nodes += Self::perft_node(board, depth - 1, table);
+      board.unmake_move(m, undo);
+    }
+
+    if let Some(table) = table {
+      table.store(board.hash, depth, nodes);
+    }
+    nodes
+  }
+}
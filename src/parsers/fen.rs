@@ -1,6 +1,10 @@
 use std::{error, fmt};
 
-use crate::structs::{BitBoard, Board, Pieces, Sides};
+use crate::{
+  lib::pos_to_alph,
+  movegen::movegen::MoveGen,
+  structs::{BitBoard, Board, Pieces, Sides},
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct FenError;
@@ -44,20 +48,113 @@ impl Fen {
       turn: side_to_play?,
       bb_pieces: pos?,
       bb_sides: [BitBoard(0); 2],
+      mailbox: [None; 64],
       white_can_oo: castle_rights?.0,
-      black_can_oo: castle_rights?.1,
-      white_can_ooo: castle_rights?.2,
+      black_can_oo: castle_rights?.2,
+      white_can_ooo: castle_rights?.1,
       black_can_ooo: castle_rights?.3,
       en_passant_square: en_passant?,
       half_moves: halfmoves?,
       full_moves: fullmoves?,
       score: 0.0,
+      hash: 0,
     };
     board.bb_sides = board.get_sides();
+    board.mailbox = board.get_mailbox();
+
+    // The one place en passant squares are lenient: GUIs and older FENs often name one after every
+    // double push, or one no pawn could have just skipped. A well formed square that isn't real, or
+    // that no pawn can capture on, is dropped rather than rejected, matching the boards make_move
+    // produces. Malformed squares are still an error from parse_en_passant. Whether a capture is
+    // possible needs the king, so that's only checked once the board is known to be valid
+    if board
+      .en_passant_square
+      .is_some_and(|square| !board.is_valid_en_passant(square))
+    {
+      board.en_passant_square = None;
+    }
+
+    if let Err(errors) = board.validate() {
+      for e in errors {
+        println!("{}", e);
+      }
+      return Err(FenError);
+    }
+
+    if board
+      .en_passant_square
+      .is_some_and(|square| !MoveGen::can_capture_en_passant(&board, square))
+    {
+      board.en_passant_square = None;
+    }
+    board.hash = board.compute_hash();
 
     Ok(board)
   }
 
+  pub fn to_fen(board: &Board) -> String {
+    let mut placement = String::new();
+
+    for rank in (0..8).rev() {
+      let mut empty = 0;
+
+      for file in 0..8 {
+        let found = board.piece_at(rank * 8 + file).map(|(side, piece)| {
+          if side == Sides::WHITE {
+            piece.to_char().to_ascii_uppercase()
+          } else {
+            piece.to_char()
+          }
+        });
+
+        match found {
+          Some(c) => {
+            if empty != 0 {
+              placement.push_str(&empty.to_string());
+              empty = 0;
+            }
+            placement.push(c);
+          }
+          None => empty += 1,
+        }
+      }
+
+      if empty != 0 {
+        placement.push_str(&empty.to_string());
+      }
+      if rank != 0 {
+        placement.push('/');
+      }
+    }
+
+    let side_to_play = if board.turn == Sides::WHITE { "w" } else { "b" };
+
+    let mut castling = String::new();
+    for (allowed, c) in [
+      (board.white_can_oo, 'K'),
+      (board.white_can_ooo, 'Q'),
+      (board.black_can_oo, 'k'),
+      (board.black_can_ooo, 'q'),
+    ] {
+      if allowed {
+        castling.push(c);
+      }
+    }
+    if castling.is_empty() {
+      castling.push('-');
+    }
+
+    let en_passant = match board.en_passant_square {
+      Some(square) => pos_to_alph(square).unwrap_or("-".to_string()),
+      None => "-".to_string(),
+    };
+
+    format!(
+      "{} {} {} {} {} {}",
+      placement, side_to_play, castling, en_passant, board.half_moves, board.full_moves
+    )
+  }
+
   fn parse_position(part: &str) -> Result<[[BitBoard; 6]; 2], FenError> {
     let ranks: Vec<_> = part.split("/").collect();
     let mut placement: [[BitBoard; 6]; 2] = [[BitBoard(0); 6], [BitBoard(0); 6]];
@@ -73,15 +170,22 @@ impl Fen {
         match piece_char.to_digit(10) {
           Some(n) => file += n as usize,
           None => {
-            let piece = Pieces::from_char(piece_char).ok_or(FenError);
+            let (side, piece) = Pieces::from_char(piece_char).ok_or(FenError)?;
+            if file > 7 {
+              return Err(FenError);
+            }
             let bit_mask = rank * 8 + file;
 
-            placement[piece.clone().unwrap().0 as usize][piece.unwrap().1 as usize].0 |=
-              1u64 << bit_mask;
+            placement[side as usize][piece as usize].0 |= 1u64 << bit_mask;
             file += 1;
           }
         }
       }
+
+      if file != 8 {
+        println!("Rank {} has {} squares, expected 8", rank + 1, file);
+        return Err(FenError);
+      }
     }
     Ok(placement)
   }
@@ -107,12 +211,12 @@ impl Fen {
       'f' => 5,
       'g' => 6,
       'h' => 7,
-      _ => 0,
+      _ => return Err(FenError),
     };
 
     let rank = match rank.to_digit(10) {
-      Some(n) => n as u8 - 1,
-      None => return Ok(None),
+      Some(n) if (1..=8).contains(&n) => n as u8 - 1,
+      _ => return Err(FenError),
     };
 
     Ok(Some(rank * 8 + file))
@@ -1,85 +1,65 @@
 use std::fmt::Error;
 
-use crate::structs::{BitBoard, Board, Move, Pieces, Sides};
-
-use crate::lib::alph_to_pos;
+use crate::{
+  movegen::movegen::MoveGen,
+  structs::{game::GameState, Board, Move},
+};
 
 use super::fen::Fen;
 
 pub struct Position;
 impl Position {
-  pub fn parse_position(args: &[&str]) -> Result<Board, Error> {
-    let mut board: Board = Board::default();
+  // The position along with the moves played, repetitions need them
+  pub fn parse_game(args: &[&str]) -> Result<GameState, Error> {
+    let mut game = GameState::new(Board::default());
     let mut token_id = 0;
     while let Some(t) = args.get(token_id) {
       match *t {
         "startpos" => {}
         "fen" => {
-          if let Ok(b) = Fen::from_fen(&args[(token_id + 1)..(token_id + 7)]) {
-            board = b;
+          let fen = args.get((token_id + 1)..(token_id + 7)).unwrap_or(&[]);
+          if let Ok(b) = Fen::from_fen(fen) {
+            game = GameState::new(b);
           } else {
             println!("Error parsing fen");
             return Err(Error);
           }
         }
         "moves" => {
-          for (move_count, m) in args[(token_id + 1)..].iter().enumerate() {
-            let (start_str, end_str) = m.split_at(2);
-            let start = match alph_to_pos(start_str) {
-              Ok(s) => s,
-              Err(e) => {
-                println!("{}", e);
-                return Err(Error);
-              }
-            };
-
-            let dest = match alph_to_pos(end_str) {
-              Ok(e) => e,
-              Err(e) => {
-                println!("{}", e);
-                return Err(Error);
-              }
+          for (ply, m) in args[(token_id + 1)..].iter().enumerate() {
+            let Some(bmove) = Self::resolve_move(m, &game.board) else {
+              println!(
+                "Error illegal move {} at ply {} in position {}",
+                m,
+                ply + 1,
+                game.board.to_fen()
+              );
+              return Err(Error);
             };
-
-            let mut bmove: Move = Default::default();
-
-            for (side, sides) in board.bb_pieces.into_iter().enumerate() {
-              for pieces in sides.into_iter() {
-                if pieces & BitBoard::from_pos(start) != BitBoard(0) {
-                  bmove.start = start;
-                  bmove.dest = dest;
-                }
-
-                for (piece, pieces) in board.bb_pieces[!Sides::from_usize(side).unwrap() as usize]
-                  .into_iter()
-                  .enumerate()
-                {
-                  if BitBoard::from_pos(dest) & pieces != BitBoard(0) {
-                    bmove.capture = Pieces::from_usize(piece);
-                  }
-                }
-
-                // if Sides::from_usize(side) != move_side
-                //   && move_side != None
-                // {
-                //   for (piece, pieces) in board.bb_pieces[side].into_iter().enumerate() {
-                //     if pieces & BitBoard::from_pos(dest) != BitBoard(0) {
-                //       println!("Move {} ({}{}) is a capture", board.full_moves, start_str, end_str);
-                //       bmove.capture = Pieces::from_usize(piece);
-                //     }
-                //   }
-                // }
-              }
-            }
-
-            board.apply_move(bmove);
+            game.make_move(bmove);
           }
+          break;
         }
         _ => {}
       }
       token_id += 1;
     }
 
-    Ok(board)
+    if let Err(errors) = game.board.validate() {
+      for e in errors {
+        println!("{}", e);
+      }
+      return Err(Error);
+    }
+
+    Ok(game)
+  }
+
+  // The legal move the GUI means, which already carries the right flags
+  fn resolve_move(uci: &str, board: &Board) -> Option<Move> {
+    let uci = uci.to_ascii_lowercase();
+    MoveGen::gen_moves(board, board.turn, true)
+      .into_iter()
+      .find(|m| m.to_string() == uci)
   }
 }
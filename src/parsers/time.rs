@@ -1,4 +1,7 @@
 use core::fmt;
+use std::time::Duration;
+
+use crate::structs::Sides;
 
 #[derive(PartialEq, Debug)]
 pub struct TimerKeeper {
@@ -16,8 +19,9 @@ impl TimerKeeper {
     }
   }
 
+  // Nothing about the clock was given
   fn blank(&self) -> bool {
-    self.time_msec != [0; 2] && self.inc_msec != [0; 2] && self.mtg != 0
+    self.time_msec == [0; 2] && self.inc_msec == [0; 2] && self.mtg == 0
   }
 }
 
@@ -45,7 +49,7 @@ pub struct Constraints {
 impl Constraints {
   pub fn new() -> Self {
     Constraints {
-      time: Some(TimerKeeper::new()),
+      time: None,
       depth: None,
       nodes: None,
       mate: None,
@@ -54,6 +58,31 @@ impl Constraints {
       ponder: false,
     }
   }
+
+  // How long the side to move may think, None when only depth or nodes limit the search. Pondering
+  // happens on the opponent's time, so it goes on until the GUI stops it
+  pub fn allocation(&self, side: Sides) -> Option<Duration> {
+    if self.infinite || self.ponder {
+      return None;
+    }
+    if let Some(movetime) = self.movetime {
+      return Some(Duration::from_millis(movetime.into()));
+    }
+
+    let time = self.time.as_ref()?;
+    // Out of time already, or the GUI's clock ran over, so move straight away
+    let remaining = time.time_msec[side as usize];
+    if remaining <= 0 {
+      return Some(Duration::from_millis(1));
+    }
+
+    // Without moves to go assume the game lasts another 30, and keep a margin for the GUI's
+    // overhead
+    let moves_to_go = if time.mtg > 0 { time.mtg as i32 } else { 30 };
+    let share = remaining / moves_to_go + time.inc_msec[side as usize] * 3 / 4;
+    let millis = share.min(remaining - 50).max(1);
+    Some(Duration::from_millis(millis as u64))
+  }
 }
 
 pub struct Time;
@@ -147,7 +176,7 @@ impl Time {
         }
 
         "movetime" => {
-          if let Some(movetime) = time_args.get(3) {
+          if let Some(movetime) = time_args.get(token_id + 1) {
             if let Ok(t) = movetime.parse::<u32>() {
               constraints.movetime = Some(t);
             }
@@ -0,0 +1,156 @@
+use core::fmt;
+
+use crate::movegen::movegen::MoveGen;
+
+use super::{Board, Move, Pieces, Sides, Undo};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+  // The side that delivered mate
+  Checkmate(Sides),
+  Stalemate,
+  InsufficientMaterial,
+  FivefoldRepetition,
+  SeventyFiveMoves,
+  // These two only end the game when a player claims them
+  ThreefoldRepetition,
+  FiftyMoves,
+}
+
+impl Outcome {
+  pub fn winner(&self) -> Option<Sides> {
+    match self {
+      Self::Checkmate(side) => Some(*side),
+      _ => None,
+    }
+  }
+
+  pub fn is_claimable(&self) -> bool {
+    matches!(self, Self::ThreefoldRepetition | Self::FiftyMoves)
+  }
+
+  // PGN style result
+  pub fn result(&self) -> &str {
+    match self.winner() {
+      Some(Sides::WHITE) => "1-0",
+      Some(Sides::BLACK) => "0-1",
+      None => "1/2-1/2",
+    }
+  }
+}
+
+impl fmt::Display for Outcome {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let reason = match self {
+      Self::Checkmate(Sides::WHITE) => "White mates",
+      Self::Checkmate(Sides::BLACK) => "Black mates",
+      Self::Stalemate => "Stalemate",
+      Self::InsufficientMaterial => "Insufficient material",
+      Self::FivefoldRepetition => "Fivefold repetition",
+      Self::SeventyFiveMoves => "75 move rule",
+      Self::ThreefoldRepetition => "Threefold repetition",
+      Self::FiftyMoves => "50 move rule",
+    };
+    write!(f, "{} {{{}}}", self.result(), reason)
+  }
+}
+
+// A board plus every move that led to it, which is what repetitions need
+#[derive(Debug, Clone)]
+pub struct GameState {
+  pub board: Board,
+  pub history: Vec<(Move, Undo)>,
+}
+
+impl GameState {
+  pub fn new(board: Board) -> Self {
+    Self {
+      board,
+      history: vec![],
+    }
+  }
+
+  pub fn make_move(&mut self, m: Move) {
+    let undo = self.board.make_move(m);
+    self.history.push((m, undo));
+  }
+
+  pub fn unmake_move(&mut self) -> Option<Move> {
+    let (m, undo) = self.history.pop()?;
+    self.board.unmake_move(m, undo);
+    Some(m)
+  }
+
+  pub fn moves(&self) -> Vec<Move> {
+    self.history.iter().map(|(m, _)| *m).collect()
+  }
+
+  // How many times the current position has come up, counting this one. Nothing before the last
+  // pawn move or capture can repeat, so the search stops there
+  pub fn repetitions(&self) -> usize {
+    1 + self
+      .history
+      .iter()
+      .rev()
+      .take(self.board.half_moves as usize)
+      .filter(|(_, undo)| undo.hash == self.board.hash)
+      .count()
+  }
+
+  pub fn outcome(&self) -> Option<Outcome> {
+    let board = self.board;
+    let side = board.turn;
+
+    // Mate and stalemate come first, mating on the fiftieth move still wins
+    if MoveGen::gen_moves(&board, side, true).is_empty() {
+      return Some(if board.in_check() {
+        Outcome::Checkmate(!side)
+      } else {
+        Outcome::Stalemate
+      });
+    }
+
+    let repetitions = self.repetitions();
+
+    if board.is_insufficient_material() {
+      Some(Outcome::InsufficientMaterial)
+    } else if repetitions >= 5 {
+      Some(Outcome::FivefoldRepetition)
+    } else if board.half_moves >= 150 {
+      Some(Outcome::SeventyFiveMoves)
+    } else if repetitions >= 3 {
+      Some(Outcome::ThreefoldRepetition)
+    } else if board.is_fifty_move_draw() {
+      Some(Outcome::FiftyMoves)
+    } else {
+      None
+    }
+  }
+}
+
+impl Board {
+  // Neither side can ever mate: bare kings, a single minor piece, or only bishops on one colour
+  pub fn is_insufficient_material(&self) -> bool {
+    const DARK_SQUARES: u64 = 0xAA55AA55AA55AA55;
+
+    let heavy = [Pieces::PAWN, Pieces::ROOK, Pieces::QUEEN];
+    if self
+      .bb_pieces
+      .iter()
+      .any(|pieces| heavy.iter().any(|p| pieces[*p as usize].0 != 0))
+    {
+      return false;
+    }
+
+    let knights =
+      self.bb_pieces[0][Pieces::KNIGHT as usize].0 | self.bb_pieces[1][Pieces::KNIGHT as usize].0;
+    let bishops =
+      self.bb_pieces[0][Pieces::BISHOP as usize].0 | self.bb_pieces[1][Pieces::BISHOP as usize].0;
+
+    if (knights | bishops).count_ones() <= 1 {
+      return true;
+    }
+
+    knights == 0 && (bishops & DARK_SQUARES == 0 || bishops & !DARK_SQUARES == 0)
+  }
+}
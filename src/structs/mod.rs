@@ -1,11 +1,18 @@
 use core::fmt;
 use std::{
-  fmt::Display,
+  fmt::{Display, Error},
   ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr},
-  vec,
 };
 
-use Iridium::pos_to_alph;
+use Iridium::{alph_to_pos, pos_to_alph};
+
+use crate::{engine::search::MAX_PLY, movegen::movegen::MoveGen, parsers::fen::Fen};
+
+use self::zobrist::KEYS;
+
+pub mod game;
+pub mod validate;
+pub mod zobrist;
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 #[repr(usize)]
@@ -81,6 +88,17 @@ impl Pieces {
       _ => None,
     }
   }
+
+  pub fn to_char(self) -> char {
+    match self {
+      Self::PAWN => 'p',
+      Self::BISHOP => 'b',
+      Self::KNIGHT => 'n',
+      Self::ROOK => 'r',
+      Self::QUEEN => 'q',
+      Self::KING => 'k',
+    }
+  }
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Clone, Copy, Debug, Default, Hash)]
@@ -91,6 +109,21 @@ impl BitBoard {
   }
 }
 
+// Pops the set squares from lowest to highest
+impl Iterator for BitBoard {
+  type Item = u8;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.0 == 0 {
+      return None;
+    }
+
+    let square = self.0.trailing_zeros() as u8;
+    self.0 &= self.0 - 1;
+    Some(square)
+  }
+}
+
 impl BitAnd for BitBoard {
   type Output = BitBoard;
 
@@ -146,6 +179,9 @@ pub struct Board {
 
   pub bb_sides: [BitBoard; 2],
 
+  // Which piece stands on each square, kept in sync with bb_pieces
+  pub mailbox: [Option<(Sides, Pieces)>; 64],
+
   pub turn: Sides,
 
   pub white_can_oo: bool,
@@ -163,6 +199,8 @@ pub struct Board {
   pub full_moves: usize,
 
   pub score: f32,
+
+  pub hash: u64,
 }
 
 impl Display for Board {
@@ -193,7 +231,7 @@ impl Display for Board {
 
 impl Default for Board {
   fn default() -> Self {
-    Self {
+    let mut board = Self {
       bb_pieces: [
         [
           BitBoard(65280), // Pawns
@@ -213,6 +251,7 @@ impl Default for Board {
         ],
       ],
       bb_sides: [BitBoard(65535), BitBoard(18446462598732840960)],
+      mailbox: [None; 64],
       turn: Sides::WHITE,
       white_can_oo: true,
       black_can_oo: true,
@@ -220,52 +259,310 @@ impl Default for Board {
       black_can_ooo: true,
       en_passant_square: None,
       half_moves: 0,
-      full_moves: 0,
+      full_moves: 1,
       score: 0.0,
-    }
+      hash: 0,
+    };
+    board.mailbox = board.get_mailbox();
+    board.hash = board.compute_hash();
+    board
   }
 }
 
+// Everything make_move can't work out again from the move itself when taking it back
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Undo {
+  pub piece: Pieces,
+  pub capture: Option<Pieces>,
+  pub white_can_oo: bool,
+  pub black_can_oo: bool,
+  pub white_can_ooo: bool,
+  pub black_can_ooo: bool,
+  pub en_passant_square: Option<u8>,
+  pub half_moves: u64,
+  pub hash: u64,
+}
+
 impl Board {
   pub fn apply_move(&mut self, m: Move) {
-    //  let op_bb: BitBoard = if BitBoard::from_pos(m.start) & self.get_sides()[0] == BitBoard(0) { self.get_sides()[0] } else { self.get_sides()[1] };
-    let side: Sides = if BitBoard::from_pos(m.start) & self.get_sides()[0] != BitBoard(0) {
-      Sides::WHITE
-    } else {
-      Sides::BLACK
+    self.make_move(m);
+  }
+
+  pub fn make_move(&mut self, m: Move) -> Undo {
+    let (start, dest) = (m.start(), m.dest());
+    let (side, moved) = self
+      .piece_at(start)
+      .unwrap_or_else(|| panic!("No piece to move on {} for {}", start, m));
+    let piece = moved as usize;
+
+    let capture = self.captured_piece(m);
+
+    let undo = Undo {
+      piece: moved,
+      capture,
+      white_can_oo: self.white_can_oo,
+      black_can_oo: self.black_can_oo,
+      white_can_ooo: self.white_can_ooo,
+      black_can_ooo: self.black_can_ooo,
+      en_passant_square: self.en_passant_square,
+      half_moves: self.half_moves,
+      hash: self.hash,
     };
-    let mut piece: usize = 0;
-    for (p, ps) in self.bb_pieces[side as usize].into_iter().enumerate() {
-      if ps & BitBoard::from_pos(m.start) != BitBoard(0) {
-        piece = p;
-        break;
+
+    // Rights and en passant are hashed out here and back in once they have been updated
+    self.hash ^= self.castling_hash() ^ self.en_passant_hash();
+
+    if m.is_en_passant() {
+      // The captured pawn sits behind the en passant square, not on it
+      let captured = if side == Sides::WHITE {
+        dest - 8
+      } else {
+        dest + 8
+      };
+      self.toggle_piece(!side, Pieces::PAWN as usize, captured);
+    } else if let Some(capture) = capture {
+      self.toggle_piece(!side, capture as usize, dest);
+    }
+
+    self.toggle_piece(side, piece, start);
+    self.toggle_piece(side, piece, dest);
+
+    self.en_passant_square = None;
+
+    // The king already moved two squares, the rook has to follow it
+    if m.is_castle() {
+      let (rook_start, rook_dest) = Move::castle_rook(m);
+      self.toggle_piece(side, Pieces::ROOK as usize, rook_start);
+      self.toggle_piece(side, Pieces::ROOK as usize, rook_dest);
+    }
+
+    self.update_castling_rights(start);
+    self.update_castling_rights(dest);
+
+    // Swap the pawn that just landed on the last rank for the promoted piece
+    if let Some(promotion) = m.promotion() {
+      self.toggle_piece(side, piece, dest);
+      self.toggle_piece(side, promotion as usize, dest);
+    }
+
+    self.hash ^= self.castling_hash() ^ self.en_passant_hash() ^ KEYS.side;
+
+    self.bb_sides = self.get_sides();
+
+    // Pawn moves and captures are irreversible so they restart the fifty move count
+    if piece == Pieces::PAWN as usize || capture.is_some() {
+      self.half_moves = 0;
+    } else {
+      self.half_moves += 1;
+    }
+
+    if side == Sides::BLACK {
+      self.full_moves += 1;
+    }
+    self.turn = !self.turn;
+
+    // Only kept when the reply can really capture, otherwise the same position would hash and
+    // repeat differently depending on whether a double push led to it
+    if m.is_double_push() {
+      let square = (start + dest) / 2;
+      if MoveGen::can_capture_en_passant(self, square) {
+        self.en_passant_square = Some(square);
+        self.hash ^= self.en_passant_hash();
       }
     }
 
-    // self.bb_pieces[side as usize][piece] =
-    //   self.bb_pieces[side as usize][piece] ^ BitBoard::from_pos(m.start);
-    self.bb_pieces[side as usize][piece] = self.bb_pieces[side as usize][piece]
-      ^ (BitBoard::from_pos(m.dest) | BitBoard::from_pos(m.start));
+    debug_assert_eq!(
+      self.hash,
+      self.compute_hash(),
+      "Incremental hash drifted after {}",
+      m
+    );
+    debug_assert_eq!(
+      self.mailbox,
+      self.get_mailbox(),
+      "Mailbox drifted after {}",
+      m
+    );
 
-    if m.capture != None {
-      let op_side = if side == Sides::WHITE {
-        Sides::BLACK
+    undo
+  }
+
+  // Takes back a move made with make_move, `undo` has to be the record it returned
+  pub fn unmake_move(&mut self, m: Move, undo: Undo) {
+    let (start, dest) = (m.start(), m.dest());
+    let piece = undo.piece as usize;
+    let (side, _) = self
+      .piece_at(dest)
+      .unwrap_or_else(|| panic!("No piece to take back on {} for {}", dest, m));
+
+    if let Some(promotion) = m.promotion() {
+      self.toggle_piece(side, promotion as usize, dest);
+      self.toggle_piece(side, piece, dest);
+    }
+
+    self.toggle_piece(side, piece, dest);
+    self.toggle_piece(side, piece, start);
+
+    if m.is_castle() {
+      let (rook_start, rook_dest) = Move::castle_rook(m);
+      self.toggle_piece(side, Pieces::ROOK as usize, rook_dest);
+      self.toggle_piece(side, Pieces::ROOK as usize, rook_start);
+    }
+
+    if m.is_en_passant() {
+      let captured = if side == Sides::WHITE {
+        dest - 8
       } else {
-        Sides::WHITE
+        dest + 8
       };
-      self.bb_pieces[op_side as usize][m.capture.unwrap() as usize] =
-        self.bb_pieces[op_side as usize][m.capture.unwrap() as usize] ^ BitBoard::from_pos(m.dest);
+      self.toggle_piece(!side, Pieces::PAWN as usize, captured);
+    } else if let Some(capture) = undo.capture {
+      self.toggle_piece(!side, capture as usize, dest);
     }
 
+    self.white_can_oo = undo.white_can_oo;
+    self.black_can_oo = undo.black_can_oo;
+    self.white_can_ooo = undo.white_can_ooo;
+    self.black_can_ooo = undo.black_can_ooo;
+    self.en_passant_square = undo.en_passant_square;
+    self.half_moves = undo.half_moves;
+    self.hash = undo.hash;
+
     self.bb_sides = self.get_sides();
-    self.full_moves += 1;
-    //println!("{}", self.full_moves);
-    // if self.full_moves % 2 == 0 {
-    //   self.turn = Sides::WHITE;
-    // } else {
-    //   self.turn = Sides::BLACK;
-    // }
+    if side == Sides::BLACK {
+      self.full_moves -= 1;
+    }
+    self.turn = !self.turn;
+  }
+
+  // Passes the turn without moving, for null move pruning. The en passant square goes since the
+  // pawn that could take on it no longer has the move, and is returned for unmake_null_move
+  pub fn make_null_move(&mut self) -> Option<u8> {
+    let en_passant_square = self.en_passant_square;
+    self.hash ^= self.en_passant_hash() ^ KEYS.side;
+    self.en_passant_square = None;
+    self.turn = !self.turn;
+
+    debug_assert_eq!(
+      self.hash,
+      self.compute_hash(),
+      "Incremental hash drifted after a null move"
+    );
+    en_passant_square
+  }
+
+  pub fn unmake_null_move(&mut self, en_passant_square: Option<u8>) {
     self.turn = !self.turn;
+    self.en_passant_square = en_passant_square;
+    self.hash ^= self.en_passant_hash() ^ KEYS.side;
+  }
+
+  // Anything besides the king and pawns, without it zugzwang is common
+  pub fn has_non_pawn_material(&self, side: Sides) -> bool {
+    [Pieces::KNIGHT, Pieces::BISHOP, Pieces::ROOK, Pieces::QUEEN]
+      .into_iter()
+      .any(|p| self.bb_pieces[side as usize][p as usize] != BitBoard(0))
+  }
+
+  pub fn piece_at(&self, square: u8) -> Option<(Sides, Pieces)> {
+    self.mailbox[square as usize]
+  }
+
+  // Moves don't carry what they capture, it's whatever enemy piece is on the destination
+  pub fn captured_piece(&self, m: Move) -> Option<Pieces> {
+    if m.is_en_passant() {
+      return Some(Pieces::PAWN);
+    }
+
+    match (self.piece_at(m.start()), self.piece_at(m.dest())) {
+      (Some((side, _)), Some((op_side, piece))) if side != op_side => Some(piece),
+      _ => None,
+    }
+  }
+
+  // Adds or removes a piece on a square, keeping the hash in sync with the bitboards
+  fn toggle_piece(&mut self, side: Sides, piece: usize, square: u8) {
+    self.bb_pieces[side as usize][piece] =
+      self.bb_pieces[side as usize][piece] ^ BitBoard::from_pos(square);
+    self.hash ^= KEYS.pieces[side as usize][piece][square as usize];
+
+    let entry = Some((side, Pieces::from_usize(piece).unwrap()));
+    self.mailbox[square as usize] = if self.mailbox[square as usize] == entry {
+      None
+    } else {
+      entry
+    };
+  }
+
+  fn castling_hash(&self) -> u64 {
+    let rights = [
+      self.white_can_oo,
+      self.white_can_ooo,
+      self.black_can_oo,
+      self.black_can_ooo,
+    ];
+
+    rights
+      .into_iter()
+      .zip(KEYS.castling)
+      .filter(|(allowed, _)| *allowed)
+      .fold(0, |hash, (_, key)| hash ^ key)
+  }
+
+  fn en_passant_hash(&self) -> u64 {
+    match self.en_passant_square {
+      Some(square) => KEYS.en_passant[(square % 8) as usize],
+      None => 0,
+    }
+  }
+
+  // Full Zobrist hash of the position, apply_move keeps `hash` up to date incrementally
+  pub fn compute_hash(&self) -> u64 {
+    let mut hash = self.castling_hash() ^ self.en_passant_hash();
+
+    for (side, pieces) in self.bb_pieces.iter().enumerate() {
+      for (piece, bb) in pieces.iter().enumerate() {
+        let mut bb = bb.0;
+        while bb != 0 {
+          let square = bb.trailing_zeros() as usize;
+          bb &= bb - 1;
+          hash ^= KEYS.pieces[side][piece][square];
+        }
+      }
+    }
+
+    if self.turn == Sides::BLACK {
+      hash ^= KEYS.side;
+    }
+    hash
+  }
+
+  // Any move from or to a king or rook home square loses the rights tied to that square
+  fn update_castling_rights(&mut self, square: u8) {
+    match square {
+      0 => self.white_can_ooo = false,
+      7 => self.white_can_oo = false,
+      4 => {
+        self.white_can_oo = false;
+        self.white_can_ooo = false;
+      }
+      56 => self.black_can_ooo = false,
+      63 => self.black_can_oo = false,
+      60 => {
+        self.black_can_oo = false;
+        self.black_can_ooo = false;
+      }
+      _ => {}
+    }
+  }
+
+  // A hundred plies without a pawn move or capture, checkmate on the last move still wins though
+  pub fn is_fifty_move_draw(&self) -> bool {
+    self.half_moves >= 100
+  }
+
+  pub fn to_fen(&self) -> String {
+    Fen::to_fen(self)
   }
 
   pub fn apply_moves(&mut self, moves: Vec<Move>) {
@@ -274,6 +571,25 @@ impl Board {
     }
   }
 
+  // Rebuilds the square to piece lookup from the bitboards
+  pub fn get_mailbox(&self) -> [Option<(Sides, Pieces)>; 64] {
+    let mut mailbox: [Option<(Sides, Pieces)>; 64] = [None; 64];
+    for (side, pieces) in self.bb_pieces.iter().enumerate() {
+      for (piece, bb) in pieces.iter().enumerate() {
+        let mut bb = bb.0;
+        while bb != 0 {
+          let square = bb.trailing_zeros() as usize;
+          bb &= bb - 1;
+          mailbox[square] = Some((
+            Sides::from_usize(side).unwrap(),
+            Pieces::from_usize(piece).unwrap(),
+          ));
+        }
+      }
+    }
+    mailbox
+  }
+
   pub fn get_sides(&self) -> [BitBoard; 2] {
     let mut bb_sides: [BitBoard; 2] = [BitBoard(0); 2];
     for (s, pieces) in self.bb_pieces.into_iter().enumerate() {
@@ -285,57 +601,187 @@ impl Board {
   }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
-pub struct Move {
-  pub start: u8,
-  pub dest: u8,
-  pub capture: Option<Pieces>,
+// Packed into 16 bits: start square in bits 0-5, destination in bits 6-11 and a flag in 12-15.
+// Captures aren't stored, Board::captured_piece looks them up
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Hash)]
+pub struct Move(pub u16);
+
+impl Move {
+  pub const QUIET: u16 = 0;
+  pub const DOUBLE_PUSH: u16 = 1;
+  pub const CASTLE: u16 = 2;
+  pub const EN_PASSANT: u16 = 3;
+  // Promotions take up flags 4 through 7, one for each piece in PROMOTIONS order
+  pub const PROMOTION: u16 = 4;
+
+  pub const PROMOTIONS: [Pieces; 4] = [Pieces::KNIGHT, Pieces::BISHOP, Pieces::ROOK, Pieces::QUEEN];
+
+  // a1 to a1 is never a real move, it stands for passing the turn. UCI writes it as 0000
+  pub const NULL: Move = Move(0);
+
+  pub fn new(start: u8, dest: u8, flag: u16) -> Self {
+    Self(start as u16 | (dest as u16) << 6 | flag << 12)
+  }
+
+  pub fn new_promotion(start: u8, dest: u8, piece: Pieces) -> Self {
+    let index = Self::PROMOTIONS
+      .iter()
+      .position(|p| *p == piece)
+      .unwrap_or(3);
+    Self::new(start, dest, Self::PROMOTION + index as u16)
+  }
+
+  pub fn start(&self) -> u8 {
+    (self.0 & 0x3f) as u8
+  }
+
+  pub fn dest(&self) -> u8 {
+    ((self.0 >> 6) & 0x3f) as u8
+  }
+
+  pub fn flag(&self) -> u16 {
+    self.0 >> 12
+  }
+
+  pub fn promotion(&self) -> Option<Pieces> {
+    if self.flag() >= Self::PROMOTION {
+      Some(Self::PROMOTIONS[(self.flag() - Self::PROMOTION) as usize])
+    } else {
+      None
+    }
+  }
+
+  pub fn is_double_push(&self) -> bool {
+    self.flag() == Self::DOUBLE_PUSH
+  }
+
+  pub fn is_castle(&self) -> bool {
+    self.flag() == Self::CASTLE
+  }
+
+  pub fn is_en_passant(&self) -> bool {
+    self.flag() == Self::EN_PASSANT
+  }
+
+  // Where the rook starts and ends up for a castling move
+  pub fn castle_rook(m: Move) -> (u8, u8) {
+    if m.dest() > m.start() {
+      (m.start() + 3, m.start() + 1)
+    } else {
+      (m.start() - 4, m.start() - 1)
+    }
+  }
+
+  // UCI moves are only squares and a promotion piece, the rest of the flag comes from the board
+  pub fn from_uci(uci: &str, board: &Board) -> Result<Self, Error> {
+    if uci.len() < 4 || uci.len() > 5 || !uci.is_ascii() {
+      println!("{} is not a valid move", uci);
+      return Err(Error);
+    }
+
+    let start = alph_to_pos(&uci[0..2])?;
+    let dest = alph_to_pos(&uci[2..4])?;
+
+    if board.piece_at(start).is_none() {
+      println!("There is no piece to move on {}", &uci[0..2]);
+      return Err(Error);
+    }
+
+    if let Some(c) = uci.chars().nth(4) {
+      return match Pieces::from_char(c) {
+        Some((_, p)) if Self::PROMOTIONS.contains(&p) => Ok(Self::new_promotion(start, dest, p)),
+        _ => {
+          println!("{} is not a valid promotion piece", c);
+          Err(Error)
+        }
+      };
+    }
+
+    let flag = match board.piece_at(start) {
+      Some((_, Pieces::PAWN)) if start.abs_diff(dest) == 16 => Self::DOUBLE_PUSH,
+      Some((_, Pieces::PAWN)) if Some(dest) == board.en_passant_square && start % 8 != dest % 8 => {
+        Self::EN_PASSANT
+      }
+      Some((_, Pieces::KING)) if start.abs_diff(dest) == 2 => Self::CASTLE,
+      _ => Self::QUIET,
+    };
+
+    Ok(Self::new(start, dest, flag))
+  }
 }
 
 impl fmt::Display for Move {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if *self == Self::NULL {
+      return write!(f, "0000");
+    }
+
     write!(
       f,
       "{}{}",
-      pos_to_alph(self.start).unwrap(),
-      pos_to_alph(self.dest).unwrap()
-    )
+      pos_to_alph(self.start()).unwrap(),
+      pos_to_alph(self.dest()).unwrap()
+    )?;
+
+    if let Some(promotion) = self.promotion() {
+      write!(f, "{}", promotion.to_char())?;
+    }
+    Ok(())
   }
 }
 
-#[derive(Clone, Debug)]
+// Long enough for any line the search can play out, kept inline so building one doesn't allocate
+#[derive(Clone, Copy, Debug)]
 pub struct Line {
-  moves: Vec<Move>,
+  moves: [Move; MAX_PLY],
+  len: usize,
 }
 
 impl Line {
   pub fn new() -> Self {
-    Self { moves: vec![] }
+    Self {
+      moves: [Move::NULL; MAX_PLY],
+      len: 0,
+    }
   }
 
   pub fn add_move(&mut self, m: Move) {
-    self.moves.push(m);
+    debug_assert!(self.len < MAX_PLY, "Line is full");
+    self.moves[self.len] = m;
+    self.len += 1;
   }
 
   pub fn extend(&mut self, line: &Self) {
-    self.moves.extend_from_slice(&line.moves);
+    for m in line.moves() {
+      self.add_move(*m);
+    }
+  }
+
+  // Becomes the move followed by the line it leads to
+  pub fn set(&mut self, m: Move, rest: &Self) {
+    self.clear();
+    self.add_move(m);
+    self.extend(rest);
+  }
+
+  pub fn clear(&mut self) {
+    self.len = 0;
   }
 
   pub fn get(self, i: usize) -> Option<Move> {
-    self.moves.get(i).copied()
+    self.moves().get(i).copied()
+  }
+
+  pub fn moves(&self) -> &[Move] {
+    &self.moves[..self.len]
   }
 }
 
 impl fmt::Display for Line {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     let _ = write!(f, "currline ");
-    for m in self.moves.clone() {
-      let _ = write!(
-        f,
-        "{}{} ",
-        pos_to_alph(m.start).unwrap(),
-        pos_to_alph(m.dest).unwrap()
-      );
+    for m in self.moves() {
+      let _ = write!(f, "{} ", m);
     }
     write!(f, "")
   }
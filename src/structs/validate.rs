@@ -0,0 +1,149 @@
+use core::fmt;
+
+use Iridium::pos_to_alph;
+
+use super::{BitBoard, Board, Pieces, Sides};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoardError {
+  OverlappingPieces(u8),
+  SidesOutOfSync,
+  MailboxOutOfSync,
+  KingCount(Sides, u32),
+  TooManyPawns(Sides, u32),
+  TooManyPieces(Sides, u32),
+  PawnOnBackRank(Sides, u8),
+  // The side and whether it's the king side right
+  CastlingRights(Sides, bool),
+  EnPassant(u8),
+  OpponentInCheck(Sides),
+}
+
+impl fmt::Display for BoardError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let square = |s: &u8| pos_to_alph(*s).unwrap_or(s.to_string());
+    match self {
+      Self::OverlappingPieces(s) => write!(f, "More than one piece on {}", square(s)),
+      Self::SidesOutOfSync => write!(f, "Side bitboards don't match the piece bitboards"),
+      Self::MailboxOutOfSync => write!(f, "Mailbox doesn't match the piece bitboards"),
+      Self::KingCount(side, n) => write!(f, "{:?} has {} kings, expected 1", side, n),
+      Self::TooManyPawns(side, n) => write!(f, "{:?} has {} pawns", side, n),
+      Self::TooManyPieces(side, n) => write!(f, "{:?} has {} pieces", side, n),
+      Self::PawnOnBackRank(side, s) => write!(f, "{:?} pawn on {}", side, square(s)),
+      Self::CastlingRights(side, oo) => write!(
+        f,
+        "{:?} can't castle {} without the king and rook on their starting squares",
+        side,
+        if *oo { "king side" } else { "queen side" }
+      ),
+      Self::EnPassant(s) => write!(f, "{} is not a possible en passant square", square(s)),
+      Self::OpponentInCheck(side) => write!(f, "{:?} is in check but it's not their turn", side),
+    }
+  }
+}
+
+impl Board {
+  // Checks the position could come up in a real game, every problem found gets reported
+  pub fn validate(&self) -> Result<(), Vec<BoardError>> {
+    let mut errors: Vec<BoardError> = vec![];
+
+    for square in 0..64 {
+      let square_bb = BitBoard::from_pos(square);
+      let count = self
+        .bb_pieces
+        .iter()
+        .flatten()
+        .filter(|bb| **bb & square_bb != BitBoard(0))
+        .count();
+
+      if count > 1 {
+        errors.push(BoardError::OverlappingPieces(square));
+      }
+    }
+
+    if self.bb_sides != self.get_sides() {
+      errors.push(BoardError::SidesOutOfSync);
+    }
+
+    if self.mailbox != self.get_mailbox() {
+      errors.push(BoardError::MailboxOutOfSync);
+    }
+
+    const BACK_RANKS: u64 = 0xff000000000000ff;
+
+    for side in [Sides::WHITE, Sides::BLACK] {
+      let pieces = self.bb_pieces[side as usize];
+
+      let kings = pieces[Pieces::KING as usize].0.count_ones();
+      if kings != 1 {
+        errors.push(BoardError::KingCount(side, kings));
+      }
+
+      let pawns = pieces[Pieces::PAWN as usize].0.count_ones();
+      if pawns > 8 {
+        errors.push(BoardError::TooManyPawns(side, pawns));
+      }
+
+      let total: u32 = pieces.iter().map(|bb| bb.0.count_ones()).sum();
+      if total > 16 {
+        errors.push(BoardError::TooManyPieces(side, total));
+      }
+
+      let mut back_rank_pawns = pieces[Pieces::PAWN as usize].0 & BACK_RANKS;
+      while back_rank_pawns != 0 {
+        let square = back_rank_pawns.trailing_zeros() as u8;
+        back_rank_pawns &= back_rank_pawns - 1;
+        errors.push(BoardError::PawnOnBackRank(side, square));
+      }
+    }
+
+    // (allowed, side, king side, king square, rook square)
+    let castles = [
+      (self.white_can_oo, Sides::WHITE, true, 4, 7),
+      (self.white_can_ooo, Sides::WHITE, false, 4, 0),
+      (self.black_can_oo, Sides::BLACK, true, 60, 63),
+      (self.black_can_ooo, Sides::BLACK, false, 60, 56),
+    ];
+
+    for (allowed, side, oo, king, rook) in castles {
+      if allowed
+        && (self.piece_at(king) != Some((side, Pieces::KING))
+          || self.piece_at(rook) != Some((side, Pieces::ROOK)))
+      {
+        errors.push(BoardError::CastlingRights(side, oo));
+      }
+    }
+
+    if let Some(square) = self.en_passant_square {
+      if !self.is_valid_en_passant(square) {
+        errors.push(BoardError::EnPassant(square));
+      }
+    }
+
+    let king = self.bb_pieces[!self.turn as usize][Pieces::KING as usize];
+    if king.0.count_ones() == 1 && self.is_square_attacked(self.king_square(!self.turn), self.turn)
+    {
+      errors.push(BoardError::OpponentInCheck(!self.turn));
+    }
+
+    if errors.is_empty() {
+      Ok(())
+    } else {
+      Err(errors)
+    }
+  }
+
+  // The square has to be behind a pawn of the side that just moved, which came from an empty square
+  pub fn is_valid_en_passant(&self, square: u8) -> bool {
+    let (rank, pushed, from) = if self.turn == Sides::WHITE {
+      (5, square.wrapping_sub(8), square + 8)
+    } else {
+      (2, square + 8, square.wrapping_sub(8))
+    };
+
+    square / 8 == rank
+      && self.piece_at(square).is_none()
+      && self.piece_at(from).is_none()
+      && self.piece_at(pushed) == Some((!self.turn, Pieces::PAWN))
+  }
+}
@@ -0,0 +1,67 @@
+// Random keys for Zobrist hashing, generated at compile time with splitmix64 so every build
+// (and every thread) hashes positions the same way
+
+pub struct ZobristKeys {
+  pub pieces: [[[u64; 64]; 6]; 2],
+  pub side: u64,
+  // White oo, white ooo, black oo, black ooo
+  pub castling: [u64; 4],
+  pub en_passant: [u64; 8],
+}
+
+pub static KEYS: ZobristKeys = ZobristKeys::generate(0x1D1D_1D1D_C0FF_EE00);
+
+impl ZobristKeys {
+  const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (state, z ^ (z >> 31))
+  }
+
+  const fn generate(seed: u64) -> Self {
+    let mut keys = Self {
+      pieces: [[[0; 64]; 6]; 2],
+      side: 0,
+      castling: [0; 4],
+      en_passant: [0; 8],
+    };
+    let mut state = seed;
+    let mut key;
+
+    let mut side = 0;
+    while side < 2 {
+      let mut piece = 0;
+      while piece < 6 {
+        let mut square = 0;
+        while square < 64 {
+          (state, key) = Self::splitmix64(state);
+          keys.pieces[side][piece][square] = key;
+          square += 1;
+        }
+        piece += 1;
+      }
+      side += 1;
+    }
+
+    (state, key) = Self::splitmix64(state);
+    keys.side = key;
+
+    let mut i = 0;
+    while i < 4 {
+      (state, key) = Self::splitmix64(state);
+      keys.castling[i] = key;
+      i += 1;
+    }
+
+    let mut file = 0;
+    while file < 8 {
+      (state, key) = Self::splitmix64(state);
+      keys.en_passant[file] = key;
+      file += 1;
+    }
+
+    keys
+  }
+}
@@ -1,70 +1,222 @@
 use std::{
-  f32::{consts::E, INFINITY},
+  sync::Arc,
   thread::{Builder, JoinHandle},
+  time::Instant,
   vec,
 };
 
-use Iridium::pos_to_alph;
-
 use crate::{
-  engine::{self, engine::Engine},
-  movegen::movegen::MoveGen,
+  engine::{
+    engine::Engine,
+    search::{is_mate, SearchControl, SearchInfo, SearchStats, MAX_DEPTH},
+    tt::{TranspositionTable, DEFAULT_HASH_MB, MAX_HASH_MB},
+  },
+  movegen::{movegen::MoveGen, movelist::MoveList},
+  parsers::time::Constraints,
   structs::{Board, Line, Move, Sides},
 };
 
+// Depth for a bare `go`, which sets no limit of its own
+const DEFAULT_DEPTH: u8 = 5;
+
+// Best move of one fixed depth search. The score is from the side to move's point of view
+pub struct SearchResult {
+  pub score: f32,
+  pub best_move: Move,
+  pub line: Line,
+  pub seldepth: u8,
+}
+
+// A search started by `go`, running on its own thread so the GUI's commands are still read
+struct SearchHandle {
+  control: Arc<SearchControl>,
+  thread: JoinHandle<()>,
+}
+
 pub struct ThreadPool {
-  pub threads: Vec<JoinHandle<(f32, Option<Move>, Line)>>,
+  pub threads: Vec<JoinHandle<(Engine, Option<SearchResult>)>>,
   pub limit: u8,
+  // Shared by every thread and kept between searches
+  pub tt: Arc<TranspositionTable>,
+  search: Option<SearchHandle>,
+  // Handed in by go so the search can be stopped from the input thread, made per search otherwise
+  control: Option<Arc<SearchControl>>,
 }
 
 impl ThreadPool {
   pub fn new(thread_limit: u8) -> Self {
+    Self::with_table(
+      thread_limit,
+      Arc::new(TranspositionTable::new(DEFAULT_HASH_MB)),
+    )
+  }
+
+  fn with_table(thread_limit: u8, tt: Arc<TranspositionTable>) -> Self {
     Self {
       threads: vec![],
       limit: thread_limit,
+      tt,
+      search: None,
+      control: None,
     }
   }
 
-  pub fn search(&mut self, board: Board, side: Sides, depth: u8) -> Option<(Move, Line)> {
-    let moves: Vec<Move> = MoveGen::gen_moves(board.clone(), side, true);
-    let num_moves = moves.len().try_into().unwrap();
-    let mut best_move: Option<(Move, Line)> = None;
-    let mut best_eval = if side == Sides::WHITE {
-      -INFINITY
-    } else {
-      INFINITY
-    };
+  pub fn resize_hash(&mut self, mb: usize) {
+    self.tt = Arc::new(TranspositionTable::new(mb.clamp(1, MAX_HASH_MB)));
+  }
 
-    let thread_limit = self.limit.min(num_moves);
+  pub fn clear_hash(&self) {
+    self.tt.clear();
+  }
 
-    let chuck_size = num_moves.div_ceil(thread_limit);
+  // Starts the search on a thread of its own, which sends the bestmove when done. A search still
+  // running is stopped first
+  pub fn go(&mut self, board: Board, constraints: Constraints) {
+    self.stop();
+    let control = Arc::new(Self::control(&board, &constraints));
+
+    let mut pool = Self::with_table(self.limit, self.tt.clone());
+    pool.control = Some(control.clone());
+    let search_control = control.clone();
+    let thread = Builder::new()
+      .name("Search".to_string())
+      .spawn(move || {
+        let best_move = pool.iterative_deepening(board, &constraints);
+        if constraints.infinite || constraints.ponder {
+          search_control.wait_for_stop();
+        }
+        println!("bestmove {}", best_move.unwrap_or(Move::NULL));
+      })
+      .expect("Failed to start the search thread");
 
-    for (i, chunk) in moves
-      .chunks(chuck_size.into())
-      .map(|x| x.to_vec())
-      .enumerate()
-    {
+    self.search = Some(SearchHandle { control, thread });
+  }
 
-      let builder = Builder::new().name(format!("Eval thread builder {}", i).into());
+  // Ends the running search, if any, once it has sent its bestmove
+  pub fn stop(&mut self) {
+    if let Some(search) = self.search.take() {
+      search.control.stop();
+      let _ = search.thread.join();
+    }
+  }
 
-      let handle = builder.spawn(move || {
-        let mut best_eval = if side == Sides::WHITE {
-          -INFINITY
-        } else {
-          INFINITY
-        };
+  fn control(board: &Board, constraints: &Constraints) -> SearchControl {
+    SearchControl::new(
+      constraints
+        .allocation(board.turn)
+        .map(|a| Instant::now() + a),
+      constraints.nodes.map(u64::from),
+    )
+  }
 
-        // let mut best_move = chunk[0];
-        // let mut best_line = Line::new();
+  // Searches depth 1, 2, 3... until a limit is hit, reporting each completed iteration. An
+  // iteration cut short is thrown away, the move played is the one from the last iteration that
+  // finished. Depth 1 always finishes, the limits only apply from depth 2 on
+  pub fn iterative_deepening(&mut self, board: Board, constraints: &Constraints) -> Option<Move> {
+    let start = Instant::now();
+    let control = match &self.control {
+      Some(control) => control.clone(),
+      None => Arc::new(Self::control(&board, constraints)),
+    };
+    self.tt.new_search();
+    let allocation = constraints.allocation(board.turn);
+    let node_limit = constraints.nodes.map(u64::from);
+
+    let max_depth = match constraints.depth {
+      Some(depth) => depth.clamp(1, MAX_DEPTH.into()) as u8,
+      None
+        if allocation.is_none()
+          && node_limit.is_none()
+          && !constraints.infinite
+          && !constraints.ponder =>
+      {
+        DEFAULT_DEPTH
+      }
+      None => MAX_DEPTH,
+    };
+
+    // Kept across iterations so each thread's move ordering history carries over
+    let mut engines: Vec<Engine> = (0..self.limit.max(1))
+      .map(|_| Engine::with_control(control.clone(), self.tt.clone()))
+      .collect();
+
+    let mut best_move = None;
+    for depth in 1..=max_depth {
+      let Some(result) = self.search(board, board.turn, depth, &mut engines) else {
+        break;
+      };
+      if control.stopped() {
+        break;
+      }
 
-        // let mut engine = Engine::new();
-        let mut engine: Engine = Engine::new();
+      best_move = Some(result.best_move);
+      control.arm();
+      println!(
+        "{}",
+        SearchInfo {
+          depth,
+          seldepth: result.seldepth,
+          score: result.score,
+          nodes: control.nodes(),
+          hashfull: self.tt.hashfull(),
+          time: start.elapsed(),
+          pv: &result.line,
+        }
+      );
 
-        let res = engine.alpha_beta_max(board.clone(), chunk, -INFINITY, INFINITY, depth - 1, Line::new());
+      // The next iteration takes a few times as long as this one, so don't start one that
+      // can't finish
+      let out_of_time = allocation.is_some_and(|a| start.elapsed() * 2 > a);
+      if is_mate(result.score) || out_of_time || control.node_limit_reached() {
+        break;
+      }
+    }
 
-        
+    let mut stats = SearchStats::default();
+    for engine in &engines {
+      stats += engine.stats;
+    }
+    println!("{}", stats);
+    best_move
+  }
+
+  // Shares the root moves out between the engines, each searching on its own thread
+  pub fn search(
+    &mut self, board: Board, side: Sides, depth: u8, engines: &mut Vec<Engine>,
+  ) -> Option<SearchResult> {
+    let moves = MoveGen::gen_moves(&board, side, true);
+    if moves.is_empty() {
+      return None;
+    }
 
-        (res.0, res.1.clone().get(0) , res.1)
+    let mut best: Option<SearchResult> = None;
+    let mut seldepth = 0;
+
+    let chuck_size = moves.len().div_ceil(engines.len().max(1));
+    let mut idle = std::mem::take(engines).into_iter();
+
+    for (i, (chunk, mut engine)) in moves
+      .chunks(chuck_size)
+      .map(MoveList::from)
+      .zip(idle.by_ref())
+      .enumerate()
+    {
+      let builder = Builder::new().name(format!("Eval thread builder {}", i));
+
+      let handle = builder.spawn(move || {
+        // Every thread searches on its own copy of the board
+        let mut board = board;
+
+        let res = engine.search_root(&mut board, chunk, depth);
+        engine.finish();
+
+        let result = res.1.get(0).map(|best_move| SearchResult {
+          score: res.0,
+          best_move,
+          line: res.1,
+          seldepth: engine.seldepth,
+        });
+        (engine, result)
       });
 
       match handle {
@@ -73,21 +225,25 @@ impl ThreadPool {
       }
     }
 
+    // Engines without any moves this time are still needed for the next search
+    engines.extend(idle);
+
     for handle in self.threads.drain(..) {
-      if let Ok((eval, m, line)) = handle.join() {
-        if (side == Sides::WHITE && eval > best_eval) || (side == Sides::BLACK && eval < best_eval)
-        // if eval > best_eval
-        {
-          best_eval = eval;
-          best_move = match m {
-              Some(m) => Some((m, line)),
-              None => None
-          }
+      if let Ok((engine, result)) = handle.join() {
+        engines.push(engine);
+        let Some(result) = result else {
+          continue;
+        };
+        seldepth = seldepth.max(result.seldepth);
+        if best.as_ref().is_none_or(|b| result.score > b.score) {
+          best = Some(result);
         }
       }
     }
 
-    println!("Best eval: {}, Best Moves {:?}", best_eval, best_move);
-    best_move
+    if let Some(b) = best.as_mut() {
+      b.seldepth = seldepth;
+    }
+    best
   }
 }